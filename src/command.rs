@@ -1,23 +1,28 @@
-use std::{collections::{HashMap, HashSet}, ffi::OsStr, io::{BufRead, BufReader, Cursor, Read}, path::{Path, PathBuf}, sync::mpsc::{self, TryRecvError}, thread::{self, JoinHandle}, time::Duration};
+use std::{collections::{BTreeMap, BTreeSet, HashMap, HashSet}, ffi::OsStr, io::{BufRead, BufReader, Cursor, Read, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicUsize, Ordering}, mpsc, Mutex}, thread::JoinHandle};
+#[cfg(windows)]
+use std::{sync::mpsc::TryRecvError, thread, time::Duration};
 
 use base64::Engine;
 use chrono::{DateTime, Utc};
 use curl::easy::Easy;
+use flate2::read::GzDecoder;
 use fs_extra::dir::CopyOptions;
 use itertools::Itertools;
-use log::{trace, error, warn};
+use log::{info, trace, error, warn};
 use ring::digest;
 use walkdir::WalkDir;
 use zip::ZipArchive;
 
-use crate::{error::{Error, Result}, schemas::{Config, Descriptor, GetPublishedFileDetailsResponseItem, PublishedFileDetails}, steam_webapi_client::SteamWebApiClient};
+use crate::{error::{Error, Result}, schemas::{CachedDigest, ChecksumVersion, Config, COLLECTION_FILETYPE, Descriptor, DesiredSet, DigestCache, FileDiff, FileDigest, GetPublishedFileDetailsResponseItem, InstallState, InstallStrategy, InstalledItem, IronyCollection, IronyModId, Manifest, MANIFEST_VERSION, Mod, RetainedVersion, Snapshot, SnapshotItem}, steam_webapi_client::SteamWebApiClient};
 
+#[allow(dead_code)]
 pub fn install_irony() -> Result<()> {
     let url = "https://github.com/bcssov/IronyModManager/releases/latest/download/win-x64.zip";
     download_and_unzip(url, get_irony_dir()?)?;
     Ok(())
 }
 
+#[allow(dead_code)]
 pub fn launch_irony() -> Result<()> {
     let mut p = WorkerProcess::spawn(&[
         "cmd".into(),
@@ -30,17 +35,54 @@ pub fn launch_irony() -> Result<()> {
     Ok(())
 }
 
-pub fn install_steamcmd() -> Result<WorkerProcess> {
-    // delete any existing steamcmd installation first
+/// Previous versions of each item kept when no limit is configured
+const DEFAULT_RETAINED_VERSIONS: usize = 1;
+
+/// Name of the collection document inside an Irony Mod Manager export zip
+const IRONY_COLLECTION_FILE: &str = "exported.json";
+
+/// Prefix of descriptors written into the game's `mod/` folder, keeping them apart from Steam's own `ugc_<id>.mod`
+const MOD_DESCRIPTOR_PREFIX: &str = "ironworks_";
+
+/// Describes a snapshot, inside its folder
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+#[cfg(windows)]
+const STEAMCMD_INSTALLER_URL: &str = "https://steamcdn-a.akamaihd.net/client/installer/steamcmd.zip";
+#[cfg(target_os = "macos")]
+const STEAMCMD_INSTALLER_URL: &str = "https://steamcdn-a.akamaihd.net/client/installer/steamcmd_osx.tar.gz";
+#[cfg(all(unix, not(target_os = "macos")))]
+const STEAMCMD_INSTALLER_URL: &str = "https://steamcdn-a.akamaihd.net/client/installer/steamcmd_linux.tar.gz";
+
+pub fn install_steamcmd(config: &Config) -> Result<WorkerProcess> {
+    // delete any existing steamcmd installation first, but keep the workshop cache, as items installed with the
+    // symlink strategy point into it. Purge it with cleanup instead
     let steamcmd_dir = get_steamcmd_dir()?;
     if steamcmd_dir.is_dir() {
         trace!("Removing existing steamcmd installation ...");
-        std::fs::remove_dir_all(&steamcmd_dir)?;
+        for entry in std::fs::read_dir(&steamcmd_dir)? {
+            let entry = entry?;
+            if entry.file_name() != "steamapps" {
+                remove_path(&entry.path())?;
+                continue;
+            }
+            for entry in std::fs::read_dir(entry.path())? {
+                let entry = entry?;
+                if entry.file_name() != "workshop" {
+                    remove_path(&entry.path())?;
+                }
+            }
+        }
         trace!("Removed existing steamcmd installation")
     }
 
-    let url = "https://steamcdn-a.akamaihd.net/client/installer/steamcmd.zip";
-    download_and_unzip(url, &steamcmd_dir)?;
+    let url = config.steamcmd_installer_url.as_deref().unwrap_or(STEAMCMD_INSTALLER_URL);
+    // windows ships a zip, everything else ships a tarball with executable bits we need to keep
+    if cfg!(windows) {
+        download_and_unzip(url, &steamcmd_dir)?;
+    } else {
+        download_and_untar_gz(url, &steamcmd_dir)?;
+    }
 
     WorkerProcess::spawn(&[
         get_steamcmd_exe()?,
@@ -48,16 +90,228 @@ pub fn install_steamcmd() -> Result<WorkerProcess> {
     )
 }
 
-pub fn calculate_local_checksum(workshop_item_id: impl AsRef<str>) -> Result<Option<String>> {
-    let mut local_dir = get_collection_dir()?;
-    local_dir.push(workshop_item_id.as_ref());
-    if local_dir.is_dir() {
-        Ok(Some(calculate_checksum(local_dir)?))
-    } else {
-        Ok(None)
+/// Checksums of several installed items, in the same order as `items`, or `None` for any not installed.
+/// Items are hashed in parallel and unchanged files are served from the digest cache,
+/// which is only written back if `update_cache` is set, so read-only commands leave the collection alone
+pub fn calculate_local_checksums(items: &[(String, ChecksumVersion)], update_cache: bool) -> Result<Vec<Option<String>>> {
+    calculate_local_checksums_each(items, update_cache)?.into_iter().collect()
+}
+
+/// Like `calculate_local_checksums`, but with a separate result per item so one unreadable item doesn't fail the rest
+pub fn calculate_local_checksums_each(items: &[(String, ChecksumVersion)], update_cache: bool) -> Result<Vec<Result<Option<String>>>> {
+    let collection_dir = get_collection_dir()?;
+    with_digest_cache(update_cache, |cache| {
+        Ok(parallel_map(items, |(id, version)| {
+            let local_dir = collection_dir.join(id);
+            if local_dir.is_dir() {
+                Ok(Some(calculate_checksum(&local_dir, *version, cache)?))
+            } else {
+                Ok(None)
+            }
+        }))
+    })
+}
+
+/// Per-file digests of an installed item, sorted by path, or `None` if it is not installed
+pub fn calculate_local_file_digests(workshop_item_id: impl AsRef<str>, update_cache: bool) -> Result<Option<Vec<FileDigest>>> {
+    let ids = [workshop_item_id.as_ref().to_owned()];
+    Ok(calculate_local_file_digests_many(&ids, update_cache)?.pop().flatten())
+}
+
+/// Per-file digests of several installed items, in the same order as `ids`, hashed in parallel
+pub fn calculate_local_file_digests_many(ids: &[String], update_cache: bool) -> Result<Vec<Option<Vec<FileDigest>>>> {
+    let collection_dir = get_collection_dir()?;
+    with_digest_cache(update_cache, |cache| {
+        parallel_map(ids, |id| {
+            let local_dir = collection_dir.join(id);
+            if local_dir.is_dir() {
+                Ok(Some(calculate_file_digests(&local_dir, cache)?))
+            } else {
+                Ok(None)
+            }
+        }).into_iter().collect()
+    })
+}
+
+/// Per-file digests of a directory, sorted by normalised relative path. Fails on any unreadable file
+fn calculate_file_digests(dir: impl AsRef<Path>, cache: &FileDigestCache) -> Result<Vec<FileDigest>> {
+    let dir = dir.as_ref();
+    let mut digests = vec![];
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.map_err(std::io::Error::from)?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = relative_file_path(dir, entry.path())?;
+        let metadata = entry.metadata().map_err(std::io::Error::from)?;
+        let digest = cache.digest(entry.path(), &metadata)?;
+        digests.push(FileDigest {
+            path,
+            size: metadata.len(),
+            sha256: base64::prelude::BASE64_STANDARD.encode(digest),
+        });
+    }
+    digests.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    Ok(digests)
+}
+
+/// `/` separated path of `path` relative to `dir`
+fn relative_file_path(dir: &Path, path: &Path) -> Result<String> {
+    Ok(path.strip_prefix(dir)
+        .map_err(|e| Error::Internal(e.to_string()))?
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .join("/"))
+}
+
+/// In-memory view of the digest cache. Lookups are read-only so it can be shared across hashing threads,
+/// newly calculated digests are collected separately and merged in when saved
+struct FileDigestCache {
+    /// Directory cache keys are relative to, or `None` to hash every file afresh
+    root: Option<PathBuf>,
+    known: HashMap<String, CachedDigest>,
+    fresh: Mutex<HashMap<String, CachedDigest>>,
+}
+
+impl FileDigestCache {
+    fn load() -> Result<FileDigestCache> {
+        let cache_file = get_digest_cache_file()?;
+        let known = if cache_file.is_file() {
+            match serde_json::from_str::<DigestCache>(&std::fs::read_to_string(&cache_file)?) {
+                Ok(cache) => cache.files,
+                Err(e) => {
+                    // only a cache, so start over rather than fail
+                    warn!("Ignoring unreadable digest cache {}: {}", cache_file.display(), e);
+                    HashMap::new()
+                },
+            }
+        } else {
+            HashMap::new()
+        };
+        Ok(FileDigestCache {
+            root: Some(get_collection_dir()?),
+            known,
+            fresh: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// A cache that remembers nothing, for when the contents on disk must actually be read
+    fn uncached() -> FileDigestCache {
+        FileDigestCache {
+            root: None,
+            known: HashMap::new(),
+            fresh: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// SHA256 of a file, from the cache if its size and modification time haven't changed.
+    /// Files are keyed by their path relative to the collection, and files outside it aren't cached
+    fn digest(&self, path: &Path, metadata: &std::fs::Metadata) -> Result<Vec<u8>> {
+        let mtime = metadata.modified()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        let Some(key) = self.root.as_deref().and_then(|root| relative_file_path(root, path).ok()) else {
+            return Ok(sha256digest(std::fs::File::open(path)?)?.as_ref().to_vec());
+        };
+        if let Some(cached) = self.known.get(&key) {
+            if cached.size == metadata.len() && cached.mtime == mtime {
+                if let Ok(digest) = base64::prelude::BASE64_STANDARD.decode(&cached.sha256) {
+                    return Ok(digest);
+                }
+            }
+        }
+        let digest = sha256digest(std::fs::File::open(path)?)?.as_ref().to_vec();
+        self.fresh.lock().expect("digest cache lock poisoned").insert(key, CachedDigest {
+            size: metadata.len(),
+            mtime,
+            sha256: base64::prelude::BASE64_STANDARD.encode(&digest),
+        });
+        Ok(digest)
+    }
+
+    /// Write the cache back out if anything new was calculated, dropping entries for files that are gone
+    fn save(self) -> Result<()> {
+        let fresh = self.fresh.into_inner().expect("digest cache lock poisoned");
+        let Some(root) = self.root.filter(|_| !fresh.is_empty()) else {
+            return Ok(());
+        };
+        let mut files = self.known;
+        files.extend(fresh);
+        files.retain(|key, _| root.join(key).is_file());
+
+        let cache_file = get_digest_cache_file()?;
+        if let Some(parent) = cache_file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_file = cache_file.with_extension("json.tmp");
+        std::fs::write(&tmp_file, serde_json::to_string(&DigestCache { files })?)?;
+        std::fs::rename(tmp_file, cache_file)?;
+        Ok(())
+    }
+}
+
+/// Run `f` with the digest cache loaded, saving whatever it learned afterwards if `update` is set.
+/// The cache is saved even if `f` fails, so work done before the failure isn't lost
+fn with_digest_cache<T>(update: bool, f: impl FnOnce(&FileDigestCache) -> Result<T>) -> Result<T> {
+    let cache = FileDigestCache::load()?;
+    let result = f(&cache);
+    if !update {
+        return result;
+    }
+    if let Err(e) = cache.save() {
+        warn!("Failed to save digest cache: {}", e);
+    }
+    result
+}
+
+/// Map `f` over `items` using one thread per available core, preserving order
+fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get()).min(items.len());
+    let next = AtomicUsize::new(0);
+    let mut results = std::thread::scope(|s| {
+        let handles = (0..workers).map(|_| s.spawn(|| {
+            let mut results = vec![];
+            loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(i) else {
+                    break;
+                };
+                results.push((i, f(item)));
+            }
+            results
+        })).collect::<Vec<_>>();
+        handles.into_iter()
+            .flat_map(|h| h.join().expect("hashing thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_unstable_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Compare per-file digests of a local copy against those expected from a manifest
+pub fn diff_file_digests(expected: &[FileDigest], local: &[FileDigest]) -> FileDiff {
+    let expected = expected.iter().map(|f| (f.path.as_str(), f)).collect::<HashMap<_, _>>();
+    let local = local.iter().map(|f| (f.path.as_str(), f)).collect::<HashMap<_, _>>();
+    FileDiff {
+        added: local.keys().filter(|p| !expected.contains_key(*p)).sorted().map(|p| p.to_string()).collect(),
+        removed: expected.keys().filter(|p| !local.contains_key(*p)).sorted().map(|p| p.to_string()).collect(),
+        changed: local.iter()
+            .filter(|(p, f)| expected.get(*p).is_some_and(|e| e != *f))
+            .map(|(p, _)| p.to_string())
+            .sorted()
+            .collect(),
     }
 }
 
+/// Compare an installed item against the per-file digests of a manifest entry, if it has any
+pub fn diff_local_files(entry: &Mod, update_cache: bool) -> Result<Option<FileDiff>> {
+    let Some(expected) = &entry.files else {
+        return Ok(None);
+    };
+    let local = calculate_local_file_digests(&entry.id, update_cache)?.unwrap_or_default();
+    Ok(Some(diff_file_digests(expected, &local)))
+}
+
 pub fn get_local_descriptors() -> Result<HashMap<String, Descriptor>> {
     let local_dir = get_collection_dir()?;
     Ok(WalkDir::new(local_dir).sort_by_file_name().max_depth(1).into_iter().filter_map(|rd| {
@@ -86,26 +340,471 @@ pub fn get_local_descriptors() -> Result<HashMap<String, Descriptor>> {
     }).collect())
 }
 
-pub fn get_local_created_timestamp(id: impl AsRef<str>) -> Result<Option<DateTime<Utc>>> {
+/// Workshop timestamp of the locally installed revision of an item, or `None` if it is not installed.
+///
+/// Items installed before the install state was tracked fall back to the folder creation time,
+/// or are treated as unknown if the filesystem doesn't record one.
+pub fn get_local_updated_timestamp(state: &InstallState, id: impl AsRef<str>) -> Result<Option<DateTime<Utc>>> {
     let mut local_dir = get_collection_dir()?;
     local_dir.push(id.as_ref());
-    if local_dir.is_dir() {
-        Ok(Some(local_dir.metadata()?.created()?.into()))
+    if !local_dir.is_dir() {
+        return Ok(None);
+    }
+
+    match state.items.get(id.as_ref()) {
+        Some(item) => {
+            // imported items have no known workshop timestamp, but they can be no older than when they were installed
+            let ts = item.time_updated.unwrap_or(item.installed_at);
+            Ok(Some(DateTime::from_timestamp(ts, 0)
+                .ok_or(Error::Internal("error constructing timestamp".to_owned()))?))
+        },
+        None => match local_dir.metadata()?.created() {
+            Ok(created) => Ok(Some(created.into())),
+            Err(e) => {
+                warn!("No install record or creation time for {}: {}", local_dir.display(), e);
+                Ok(None)
+            },
+        },
+    }
+}
+
+/// Total size in bytes of the files of an installed item, or `None` if it is not installed
+pub fn get_local_size(id: impl AsRef<str>) -> Result<Option<u64>> {
+    let mut local_dir = get_collection_dir()?;
+    local_dir.push(id.as_ref());
+    if !local_dir.is_dir() {
+        return Ok(None);
+    }
+    Ok(Some(get_dir_size(&local_dir)?))
+}
+
+pub fn is_installed(id: impl AsRef<str>) -> Result<bool> {
+    Ok(get_collection_dir()?.join(id.as_ref()).is_dir())
+}
+
+/// When an item was installed, from the install state or failing that the folder creation time
+pub fn get_local_installed_timestamp(state: &InstallState, id: impl AsRef<str>) -> Result<Option<DateTime<Utc>>> {
+    let mut local_dir = get_collection_dir()?;
+    local_dir.push(id.as_ref());
+    if !local_dir.is_dir() {
+        return Ok(None);
+    }
+    match state.items.get(id.as_ref()) {
+        Some(item) => Ok(DateTime::from_timestamp(item.installed_at, 0)),
+        None => Ok(local_dir.metadata()?.created().ok().map(Into::into)),
+    }
+}
+
+pub fn load_install_state() -> Result<InstallState> {
+    let state_file = get_install_state_file()?;
+    if state_file.is_file() {
+        Ok(serde_json::from_str(&std::fs::read_to_string(state_file)?)?)
     } else {
-        Ok(None)
+        Ok(InstallState::default())
+    }
+}
+
+pub fn save_install_state(state: &InstallState) -> Result<()> {
+    let state_file = get_install_state_file()?;
+    if let Some(parent) = state_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // write to a temp file first so a crash can't leave a truncated state file behind
+    let tmp_file = state_file.with_extension("json.tmp");
+    std::fs::write(&tmp_file, serde_json::to_string_pretty(state)?)?;
+    std::fs::rename(tmp_file, state_file)?;
+    Ok(())
+}
+
+/// Record freshly copied items in the install state, given their checksums with the latest algorithm.
+/// `entry.time_updated` should only be set if it is known to be the revision that was downloaded
+pub fn record_installed_items<'a>(items: impl Iterator<Item = (&'a Mod, &'a str)>) -> Result<()> {
+    let mut state = load_install_state()?;
+    for (entry, checksum) in items {
+        state.items.insert(entry.id.clone(), InstalledItem {
+            title: entry.name.clone(),
+            time_updated: entry.time_updated,
+            installed_at: Utc::now().timestamp(),
+            checksum: checksum.to_owned(),
+            checksum_version: ChecksumVersion::LATEST,
+            dependency: entry.dependency,
+            workshop_children: entry.dependencies.clone(),
+            rolled_back_from: None,
+        });
+    }
+    save_install_state(&state)
+}
+
+/// Read a manifest, refusing formats newer than this build understands
+pub fn load_manifest(file: impl AsRef<Path>) -> Result<Manifest> {
+    let manifest = serde_json::from_str::<Manifest>(&std::fs::read_to_string(file)?)?;
+    if manifest.version > MANIFEST_VERSION {
+        return Err(Error::InvalidInput(format!(
+            "manifest version {} is newer than the supported version {}, please update ironworks",
+            manifest.version,
+            MANIFEST_VERSION)));
+    }
+    Ok(manifest)
+}
+
+/// Read a hand-edited desired set file
+pub fn load_desired_set(file: impl AsRef<Path>) -> Result<DesiredSet> {
+    Ok(toml::from_str(&std::fs::read_to_string(file)?)?)
+}
+
+/// The lock file generated from a desired set file lives next to it, with a `.lock` extension
+pub fn get_lock_file(desired_file: impl AsRef<Path>) -> PathBuf {
+    desired_file.as_ref().with_extension("lock")
+}
+
+/// Hold or unhold items, returning those whose hold changed along with everything held afterwards
+pub fn set_held(ids: &[String], held: bool) -> Result<(Vec<String>, Vec<String>)> {
+    let mut state = load_install_state()?;
+    let changed = ids.iter()
+        .filter(|id| if held { state.held.insert((*id).clone()) } else { state.held.remove(*id) })
+        .cloned()
+        .collect::<Vec<_>>();
+    if !changed.is_empty() {
+        save_install_state(&state)?;
     }
+    Ok((changed, state.held.into_iter().collect()))
 }
 
-pub fn download_workshop_item(workshop_item_id: impl AsRef<str>) -> Result<WorkerProcess> {
+/// Remember the load order of the collection, e.g. from an imported playset
+pub fn save_load_order(ids: &[String]) -> Result<()> {
+    let mut state = load_install_state()?;
+    state.load_order = ids.to_vec();
+    save_install_state(&state)
+}
+
+/// Workshop ids of the enabled mods in a Paradox Launcher playset, in load order.
+/// Mods that didn't come from the Workshop are skipped with a warning
+pub fn read_launcher_playset(playset_name: impl AsRef<str>) -> Result<Vec<String>> {
+    let db_file = get_launcher_db_file()?;
+    if !db_file.is_file() {
+        return Err(Error::InvalidInput(format!("Paradox Launcher database not found at {}", db_file.display())));
+    }
+    let conn = rusqlite::Connection::open_with_flags(&db_file, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    read_playset(&conn, playset_name.as_ref())
+}
+
+fn read_playset(conn: &rusqlite::Connection, playset_name: &str) -> Result<Vec<String>> {
+    let found = conn.query_row("SELECT COUNT(*) FROM playsets WHERE name = ?1", [playset_name], |row| row.get::<_, i64>(0))?;
+    if found == 0 {
+        let mut stmt = conn.prepare("SELECT name FROM playsets ORDER BY name")?;
+        let names = stmt.query_map([], |row| row.get::<_, String>(0))?.collect::<rusqlite::Result<Vec<_>>>()?;
+        return Err(Error::InvalidInput(format!("no playset named '{}' in the Paradox Launcher, found: {}", playset_name, names.join(", "))));
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT m.steamId, m.gameRegistryId, m.displayName
+        FROM playsets p
+        JOIN playsets_mods pm ON pm.playsetId = p.id
+        JOIN mods m ON m.id = pm.modId
+        WHERE p.name = ?1 AND pm.enabled = 1
+        ORDER BY pm.position")?;
+    let rows = stmt.query_map([playset_name], |row| Ok((
+        sql_text(row.get(0)?),
+        sql_text(row.get(1)?),
+        sql_text(row.get(2)?),
+    )))?;
+
+    let mut ids = vec![];
+    for row in rows {
+        let (steam_id, game_registry_id, display_name) = row?;
+        // workshop mods are registered as mod/ugc_<id>.mod, in case the launcher didn't fill in steamId,
+        // and the collection's own descriptors are registered as local mods named after the id
+        let id = steam_id.filter(|id| id.parse::<u64>().is_ok()).or_else(|| game_registry_id.as_deref()
+            .and_then(|r| r.strip_prefix("mod/"))
+            .and_then(|r| r.strip_prefix("ugc_").or_else(|| r.strip_prefix(MOD_DESCRIPTOR_PREFIX)))
+            .and_then(|r| r.strip_suffix(".mod"))
+            .filter(|id| id.parse::<u64>().is_ok())
+            .map(str::to_owned));
+        match id {
+            Some(id) => ids.push(id),
+            None => warn!("Skipping '{}' from playset '{}', it isn't from the Steam Workshop",
+                display_name.or(game_registry_id).as_deref().unwrap_or("<no name>"),
+                playset_name),
+        }
+    }
+    Ok(ids)
+}
+
+/// Installed items in load order: the recorded load order first, then anything else installed by name
+pub fn get_load_order(descriptors: &HashMap<String, Descriptor>, state: &InstallState) -> Vec<String> {
+    state.load_order.iter()
+        .filter(|id| descriptors.contains_key(*id))
+        .unique()
+        .chain(descriptors.iter()
+            .filter(|(id, _)| !state.load_order.contains(id))
+            .sorted_by_key(|(_, desc)| desc.name.to_lowercase())
+            .map(|(id, _)| id))
+        .cloned()
+        .collect()
+}
+
+/// Read an Irony Mod Manager collection export as a manifest, keeping its load order.
+/// Mods that didn't come from the Workshop are skipped with a warning
+pub fn read_irony_collection(file: impl AsRef<Path>) -> Result<Manifest> {
+    let mut archive = ZipArchive::new(std::fs::File::open(file)?)?;
+    let collection = serde_json::from_reader::<_, IronyCollection>(archive.by_name(IRONY_COLLECTION_FILE)?)?;
+
+    let mut mods = vec![];
+    for (i, descriptor) in collection.mods.iter().enumerate() {
+        let name = collection.mod_names.get(i).cloned();
+        // older exports have no ids, but workshop descriptors are named after them
+        let id = collection.mod_ids.get(i).and_then(|ids| ids.steam_id).map(|id| id.to_string())
+            .or_else(|| descriptor.strip_prefix("mod/ugc_")
+                .and_then(|d| d.strip_suffix(".mod"))
+                .filter(|id| id.parse::<u64>().is_ok())
+                .map(str::to_owned));
+        match id {
+            Some(id) => mods.push(Mod {
+                id,
+                name,
+                ..Default::default()
+            }),
+            None => warn!("Skipping '{}' from Irony collection '{}', it isn't from the Steam Workshop",
+                name.as_deref().unwrap_or(descriptor),
+                collection.name),
+        }
+    }
+    Ok(Manifest {
+        version: MANIFEST_VERSION,
+        mods,
+    })
+}
+
+/// Write a manifest as an Irony Mod Manager collection export, in the order of its mods.
+/// The collection is named after the file
+pub fn write_irony_collection(file: impl AsRef<Path>, manifest: &Manifest) -> Result<()> {
+    let file = file.as_ref();
+    let collection = IronyCollection {
+        name: file.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+        game: "Stellaris".to_owned(),
+        // irony matches mods by descriptor name, and workshop subscriptions are named after their id
+        mods: manifest.mods.iter().map(|m| format!("mod/ugc_{}.mod", m.id)).collect(),
+        mod_names: manifest.mods.iter().map(|m| m.name.clone().unwrap_or_default()).collect(),
+        mod_ids: manifest.mods.iter().map(|m| IronyModId {
+            paradox_id: None,
+            steam_id: m.id.parse().ok(),
+        }).collect(),
+        is_selected: true,
+        ..Default::default()
+    };
+
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(file)?);
+    zip.start_file(IRONY_COLLECTION_FILE, zip::write::SimpleFileOptions::default())?;
+    zip.write_all(serde_json::to_string_pretty(&collection)?.as_bytes())?;
+    zip.finish()?;
+    Ok(())
+}
+
+/// Write a descriptor into the game's `mod/` folder pointing at an installed item, so the game and launcher can find it.
+/// Returns the name the game knows it by, e.g. `mod/ironworks_<id>.mod`
+pub fn write_mod_descriptor(id: impl AsRef<str>) -> Result<String> {
+    let id = id.as_ref();
+    let item_dir = get_collection_dir()?.join(id);
+    let contents = std::fs::read_to_string(item_dir.join("descriptor.mod"))?;
+    // the game wants forward slashes everywhere
+    let path = dunce::canonicalize(&item_dir)?.display().to_string().replace('\\', "/");
+
+    let key_of = |line: &str| line.split('=').next().unwrap_or_default().trim().to_owned();
+    let mut descriptor = contents.lines()
+        .filter(|line| !matches!(key_of(line).as_str(), "path" | "archive"))
+        .join("\n");
+    if !contents.lines().any(|line| key_of(line) == "remote_file_id") {
+        descriptor.push_str(&format!("\nremote_file_id=\"{}\"", id));
+    }
+    descriptor.push_str(&format!("\npath=\"{}\"\n", path));
+
+    let mod_dir = get_stellaris_mod_dir()?;
+    std::fs::create_dir_all(&mod_dir)?;
+    let file_name = format!("{}{}.mod", MOD_DESCRIPTOR_PREFIX, id);
+    std::fs::write(mod_dir.join(&file_name), descriptor)?;
+    Ok(format!("mod/{}", file_name))
+}
+
+/// Remove descriptors previously written by `write_mod_descriptor` for items not in `keep`, returning their ids
+pub fn remove_stale_mod_descriptors(keep: &HashSet<String>) -> Result<Vec<String>> {
+    let mod_dir = get_stellaris_mod_dir()?;
+    let mut removed = vec![];
+    if !mod_dir.is_dir() {
+        return Ok(removed);
+    }
+    for entry in std::fs::read_dir(mod_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let id = file_name.strip_prefix(MOD_DESCRIPTOR_PREFIX).and_then(|f| f.strip_suffix(".mod"));
+        if let Some(id) = id {
+            if !keep.contains(id) {
+                std::fs::remove_file(entry.path())?;
+                removed.push(id.to_owned());
+            }
+        }
+    }
+    removed.sort_unstable();
+    Ok(removed)
+}
+
+/// Set the mods the game loads, in order, keeping any other settings in `dlc_load.json`. Returns the file written
+pub fn write_dlc_load(enabled_mods: &[String]) -> Result<PathBuf> {
+    let dlc_load_file = get_stellaris_user_dir()?.join("dlc_load.json");
+    let mut dlc_load = if dlc_load_file.is_file() {
+        serde_json::from_str::<serde_json::Value>(&std::fs::read_to_string(&dlc_load_file)?)?
+    } else {
+        serde_json::json!({ "disabled_dlcs": [] })
+    };
+    let Some(object) = dlc_load.as_object_mut() else {
+        return Err(Error::InvalidInput(format!("{} is not a JSON object", dlc_load_file.display())));
+    };
+    object.insert("enabled_mods".to_owned(), serde_json::json!(enabled_mods));
+    if let Some(parent) = dlc_load_file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&dlc_load_file, serde_json::to_string_pretty(&dlc_load)?)?;
+    Ok(dlc_load_file)
+}
+
+/// Create or replace a Paradox Launcher playset holding `mods` in order, as (workshop id, name, game registry name).
+/// The descriptors are registered with the launcher as local mods, if it doesn't know about them yet
+pub fn write_launcher_playset(playset_name: impl AsRef<str>, mods: &[(String, String, String)]) -> Result<()> {
+    let db_file = get_launcher_db_file()?;
+    if !db_file.is_file() {
+        return Err(Error::InvalidInput(format!("Paradox Launcher database not found at {}", db_file.display())));
+    }
+    let collection_dir = get_collection_dir()?;
+    let mods = mods.iter()
+        .map(|(id, name, registry_name)| {
+            // the same path as the descriptor gives
+            let dir_path = dunce::canonicalize(collection_dir.join(id))?.display().to_string().replace('\\', "/");
+            Ok((name.clone(), registry_name.clone(), dir_path))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut conn = rusqlite::Connection::open(&db_file)?;
+    write_playset(&mut conn, playset_name.as_ref(), &mods, Utc::now())
+}
+
+/// Write a playset of `mods` in order, as (name, game registry name, directory)
+fn write_playset(conn: &mut rusqlite::Connection, playset_name: &str, mods: &[(String, String, String)], now: DateTime<Utc>) -> Result<()> {
+    // the launcher keeps playset times as datetimes, but mod times as milliseconds
+    let now_datetime = now.format("%Y-%m-%d %H:%M:%S%.3f +00:00").to_string();
+    let tx = conn.transaction()?;
+
+    let existing = tx.query_row("SELECT id FROM playsets WHERE name = ?1", [playset_name], |row| row.get::<_, rusqlite::types::Value>(0));
+    let playset_id = match existing {
+        Ok(id) => {
+            tx.execute("DELETE FROM playsets_mods WHERE playsetId = ?1", [&id])?;
+            tx.execute("UPDATE playsets SET updatedOn = ?2 WHERE id = ?1", rusqlite::params![id, now_datetime])?;
+            id
+        },
+        Err(rusqlite::Error::QueryReturnedNoRows) => {
+            let id = rusqlite::types::Value::Text(new_uuid()?);
+            tx.execute(
+                "INSERT INTO playsets (id, name, isActive, loadOrder, createdOn, isRemoved, hasNotApprovedChanges)
+                VALUES (?1, ?2, 0, 'custom', ?3, 0, 0)",
+                rusqlite::params![id, playset_name, now_datetime])?;
+            id
+        },
+        Err(e) => return Err(e.into()),
+    };
+
+    for (position, (name, registry_name, dir_path)) in mods.iter().enumerate() {
+        let existing = tx.query_row(
+            "SELECT id FROM mods WHERE gameRegistryId = ?1",
+            [registry_name],
+            |row| row.get::<_, rusqlite::types::Value>(0));
+        let mod_id = match existing {
+            Ok(mod_id) => {
+                // the collection may have moved since
+                tx.execute(
+                    "UPDATE mods SET name = ?2, displayName = ?2, dirPath = ?3, source = 'local', status = 'ready_to_play' WHERE id = ?1",
+                    rusqlite::params![mod_id, name, dir_path])?;
+                mod_id
+            },
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                let mod_id = rusqlite::types::Value::Text(new_uuid()?);
+                tx.execute(
+                    "INSERT INTO mods (id, gameRegistryId, name, displayName, dirPath, status, source, isNew, createdOn)
+                    VALUES (?1, ?2, ?3, ?3, ?4, 'ready_to_play', 'local', 0, ?5)",
+                    rusqlite::params![mod_id, registry_name, name, dir_path, now.timestamp_millis()])?;
+                mod_id
+            },
+            Err(e) => return Err(e.into()),
+        };
+        tx.execute(
+            "INSERT INTO playsets_mods (playsetId, modId, enabled, position) VALUES (?1, ?2, 1, ?3)",
+            rusqlite::params![playset_id, mod_id, position as i64])?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+/// Random version 4 UUID, as used for launcher database ids
+fn new_uuid() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    ring::rand::SecureRandom::fill(&ring::rand::SystemRandom::new(), &mut bytes)
+        .map_err(|_| Error::Internal("failed to generate random id".to_owned()))?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes.iter().map(|b| format!("{:02x}", b)).join("");
+    Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+/// The launcher isn't consistent about column types, so take text or numbers alike
+fn sql_text(value: rusqlite::types::Value) -> Option<String> {
+    match value {
+        rusqlite::types::Value::Text(s) => Some(s),
+        rusqlite::types::Value::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+/// Download several workshop items in a single steamcmd session, saving the login and startup for each.
+/// Use `parse_download_result` on the output lines to find out how each item went.
+pub fn download_workshop_items(workshop_item_ids: impl IntoIterator<Item = impl AsRef<str>>) -> Result<WorkerProcess> {
     ensure_init()?;
     let stellaris_appid = "281990";
 
-    WorkerProcess::spawn(&[
-        get_steamcmd_exe()?,
-        "+login anonymous".into(),
-        format!("+workshop_download_item {} {}", stellaris_appid, workshop_item_id.as_ref()).into(),
-        "+quit".into(),
-    ])
+    let mut args = vec![
+        get_steamcmd_exe()?.into_os_string(),
+        "+login".into(),
+        "anonymous".into(),
+    ];
+    for id in workshop_item_ids {
+        args.push("+workshop_download_item".into());
+        args.push(stellaris_appid.into());
+        args.push(id.as_ref().into());
+    }
+    args.push("+quit".into());
+
+    WorkerProcess::spawn(&args)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadResult {
+    Success,
+    Failed(String),
+}
+
+/// Parse a line of steamcmd output for the result of a `+workshop_download_item` command, e.g.
+/// `Success. Downloaded item 123 to "..." (456 bytes)` or `ERROR! Download item 123 failed (Failure).`
+pub fn parse_download_result(line: &str) -> Option<(String, DownloadResult)> {
+    let item_id_after = |s: &str, prefix: &str| {
+        s.find(prefix).and_then(|i| {
+            let id = s[i + prefix.len()..].split_whitespace().next()?;
+            id.chars().all(|c| c.is_ascii_digit()).then(|| id.to_owned())
+        })
+    };
+
+    if let Some(rest) = line.strip_prefix("Success.") {
+        item_id_after(rest, "Downloaded item ").map(|id| (id, DownloadResult::Success))
+    } else if let Some(rest) = line.strip_prefix("ERROR!") {
+        item_id_after(rest, "item ").map(|id| (id, DownloadResult::Failed(rest.trim().to_owned())))
+    } else {
+        None
+    }
 }
 
 pub fn copy_downloaded_workshop_item(workshop_item_id: impl AsRef<str>) -> Result<()> {
@@ -118,39 +817,766 @@ pub fn copy_downloaded_workshop_item(workshop_item_id: impl AsRef<str>) -> Resul
     if source_dir.is_dir() {
         let mut dest_dir = get_collection_dir()?;
         dest_dir.push(workshop_item_id.as_ref());
-        trace!("Copying {} to {}", source_dir.display(), dest_dir.display());
-        if dest_dir.exists() {
-            trace!("Destination already exists, deleting");
-            if dest_dir.is_file() {
-                std::fs::remove_file(&dest_dir)?;
+
+        // copy next to the collection first, so a failed copy never touches the installed version
+        let staging_dir = get_staging_dir()?;
+        std::fs::create_dir_all(&staging_dir)?;
+        let staged_dir = staging_dir.join(workshop_item_id.as_ref());
+        remove_path(&staged_dir)?;
+        let strategy = get_config_or_default()?.install_strategy.unwrap_or_default();
+        let staged = stage_workshop_item(&source_dir, &staged_dir, strategy);
+        let replaced = match staged.and_then(|_| swap_into_place(&staged_dir, &dest_dir)) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                if let Err(cleanup) = remove_path(&staged_dir) {
+                    warn!("Failed to clean up {}: {}", staged_dir.display(), cleanup);
+                }
+                return Err(e);
+            },
+        };
+        if let Some(replaced) = replaced {
+            if let Err(e) = retain_version(workshop_item_id.as_ref(), &replaced) {
+                warn!("Failed to keep the previous version of {}: {}", workshop_item_id.as_ref(), e);
+                remove_path(&replaced)?;
+            }
+        }
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound, 
+            format!("Directory {} not found", source_dir.display())).into())
+    }
+}
+
+/// Put a downloaded item at `staged_dir` as `strategy` says, copying instead where the filesystem can't do that
+fn stage_workshop_item(source_dir: &Path, staged_dir: &Path, strategy: InstallStrategy) -> Result<()> {
+    match strategy {
+        InstallStrategy::Copy => {},
+        InstallStrategy::Move => {
+            trace!("Moving {} to {}", source_dir.display(), staged_dir.display());
+            match std::fs::rename(source_dir, staged_dir) {
+                Ok(()) => return Ok(()),
+                // most likely steamcmd and the collection are on different filesystems
+                Err(e) => warn!("Could not move {}, copying it instead: {}", source_dir.display(), e),
+            }
+        },
+        InstallStrategy::Hardlink | InstallStrategy::Reflink => {
+            let (method, verb) = if strategy == InstallStrategy::Hardlink {
+                (ShareMethod::HardLink, "hard link")
             } else {
-                std::fs::remove_dir_all(&dest_dir)?;
+                (ShareMethod::Reflink, "reflink")
+            };
+            trace!("Linking {} to {}", source_dir.display(), staged_dir.display());
+            if clone_tree(source_dir, staged_dir, &[method])? > 0 {
+                warn!("Could not {} every file of {}, copied the rest instead", verb, source_dir.display());
+            }
+            return verify_staged_copy(source_dir, staged_dir);
+        },
+        InstallStrategy::Symlink => {
+            trace!("Linking {} to {}", staged_dir.display(), source_dir.display());
+            match symlink_dir(&dunce::canonicalize(source_dir)?, staged_dir) {
+                Ok(()) => return Ok(()),
+                // e.g. Windows without developer mode
+                Err(e) => warn!("Could not link to {}, copying it instead: {}", source_dir.display(), e),
+            }
+        },
+    }
+
+    trace!("Copying {} to {}", source_dir.display(), staged_dir.display());
+    fs_extra::copy_items(&[source_dir], staged_dir, &CopyOptions::new().copy_inside(true))?;
+    verify_staged_copy(source_dir, staged_dir)?;
+    if strategy == InstallStrategy::Move {
+        remove_path(source_dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(original, link)
+}
+
+#[cfg(unix)]
+fn symlink_dir(original: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+/// Check a staged copy has the same files with the same sizes as what it was copied from
+fn verify_staged_copy(source_dir: &Path, staged_dir: &Path) -> Result<()> {
+    let list_files = |dir: &Path| -> Result<Vec<(String, u64)>> {
+        let mut files = vec![];
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if entry.file_type().is_file() {
+                files.push((relative_file_path(dir, entry.path())?, entry.metadata().map_err(std::io::Error::from)?.len()));
+            }
+        }
+        Ok(files)
+    };
+    if list_files(source_dir)? == list_files(staged_dir)? {
+        Ok(())
+    } else {
+        Err(Error::Internal(format!("copy of {} at {} is incomplete", source_dir.display(), staged_dir.display())))
+    }
+}
+
+/// Replace `dest` with `staged` by renaming, putting the previous version back if that fails.
+/// Returns where the previous version was moved aside to, for the caller to keep or remove
+fn swap_into_place(staged: &Path, dest: &Path) -> Result<Option<PathBuf>> {
+    let previous = staged.with_extension("previous");
+    if previous.symlink_metadata().is_ok() {
+        if dest.symlink_metadata().is_ok() {
+            remove_path(&previous)?;
+        } else {
+            // left behind by an interrupted swap, so this is the last good version
+            warn!("Restoring {} left behind by an interrupted install", dest.display());
+            std::fs::rename(&previous, dest)?;
+        }
+    }
+
+    let had_previous = dest.symlink_metadata().is_ok();
+    if had_previous {
+        trace!("Moving existing {} aside", dest.display());
+        std::fs::rename(dest, &previous)?;
+    }
+    trace!("Moving {} into place at {}", staged.display(), dest.display());
+    if let Err(e) = std::fs::rename(staged, dest) {
+        if had_previous {
+            match std::fs::rename(&previous, dest) {
+                Ok(()) => warn!("Install of {} failed, restored previous version", dest.display()),
+                Err(restore) => error!("Install of {} failed and the previous version could not be restored from {}: {}",
+                    dest.display(), previous.display(), restore),
+            }
+        }
+        return Err(e.into());
+    }
+    Ok(had_previous.then_some(previous))
+}
+
+/// Keep a replaced version of an item so it can be rolled back to, then drop old versions beyond the configured limits.
+/// Must be called before the replacement is recorded in the install state
+fn retain_version(id: &str, replaced: &Path) -> Result<()> {
+    let config = get_config_or_default()?;
+    let keep = config.retained_versions.unwrap_or(DEFAULT_RETAINED_VERSIONS);
+    // a link points at steamcmd's copy, which has already moved on to the new version
+    if keep == 0 || replaced.is_symlink() {
+        return remove_path(replaced);
+    }
+
+    let mut state = load_install_state()?;
+    let now = Utc::now().timestamp();
+    let version = match state.items.get(id) {
+        Some(item) => RetainedVersion {
+            title: item.title.clone(),
+            time_updated: item.time_updated,
+            installed_at: item.installed_at,
+            checksum: item.checksum.clone(),
+            checksum_version: item.checksum_version,
+            retained_at: now,
+            size: get_dir_size(replaced)?,
+        },
+        None => RetainedVersion {
+            // installed before ironworks kept track, so all we know is what's on disk
+            title: None,
+            time_updated: None,
+            installed_at: get_untracked_installed_at(replaced)?,
+            checksum: with_digest_cache(true, |cache| calculate_checksum_v2(replaced, cache))?,
+            checksum_version: ChecksumVersion::V2,
+            retained_at: now,
+            size: get_dir_size(replaced)?,
+        },
+    };
+
+    let version_dir = get_version_dir(id, version.installed_at)?;
+    if let Some(parent) = version_dir.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    remove_path(&version_dir)?;
+    trace!("Keeping previous version of {} at {}", id, version_dir.display());
+    std::fs::rename(replaced, &version_dir)?;
+    let versions = state.retained.entry(id.to_owned()).or_default();
+    versions.retain(|v| v.installed_at != version.installed_at);
+    versions.push(version);
+
+    prune_retained_versions(&mut state, &get_versions_dir()?, keep, config.retained_versions_max_mb)?;
+    save_install_state(&state)
+}
+
+/// Drop the oldest kept versions under `versions_dir` until each item has at most `keep`, and all of them fit in `max_mb`
+fn prune_retained_versions(state: &mut InstallState, versions_dir: &Path, keep: usize, max_mb: Option<u64>) -> Result<()> {
+    let mut dropped = vec![];
+    for (id, versions) in state.retained.iter_mut() {
+        versions.sort_by_key(|v| v.retained_at);
+        let excess = versions.len().saturating_sub(keep);
+        dropped.extend(versions.drain(..excess).map(|v| (id.clone(), v)));
+    }
+    if let Some(max_mb) = max_mb {
+        let max_size = max_mb * 1024 * 1024;
+        let mut total_size = state.retained.values().flatten().map(|v| v.size).sum::<u64>();
+        while total_size > max_size {
+            // each list is sorted, so the oldest overall is at the front of one of them
+            let Some(id) = state.retained.iter()
+                .filter_map(|(id, versions)| versions.first().map(|v| (id, v.retained_at)))
+                .min_by_key(|(_, retained_at)| *retained_at)
+                .map(|(id, _)| id.clone()) else {
+                break;
+            };
+            let version = state.retained.get_mut(&id).expect("id was just found").remove(0);
+            total_size -= version.size;
+            dropped.push((id, version));
+        }
+    }
+    state.retained.retain(|_, versions| !versions.is_empty());
+
+    for (id, version) in dropped {
+        trace!("Dropping version of {} installed at {}", id, version.installed_at);
+        let item_dir = versions_dir.join(&id);
+        remove_path(&item_dir.join(version.installed_at.to_string()))?;
+        if item_dir.is_dir() && std::fs::read_dir(&item_dir)?.next().is_none() {
+            std::fs::remove_dir(item_dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Swap a kept version of an item back into the collection, keeping the current version in its place.
+/// Picks the latest version installed at or before `to`, or the latest of all. Returns the version restored
+pub fn rollback_item(id: impl AsRef<str>, to: Option<i64>) -> Result<RetainedVersion> {
+    let id = id.as_ref();
+    let state = load_install_state()?;
+    let chosen = choose_retained_version(&state, id, to)?;
+    let current = state.items.get(id).cloned();
+
+    let dest_dir = get_collection_dir()?.join(id);
+    let replaced = swap_into_place(&get_version_dir(id, chosen.installed_at)?, &dest_dir)?;
+    let untracked = match (&current, &replaced) {
+        (None, Some(replaced)) => Some(get_untracked_installed_at(replaced)?),
+        _ => None,
+    };
+    let rolled_back_from = get_rolled_back_from(current.as_ref(), untracked);
+
+    // forget the restored version before keeping the one it replaced, so that isn't pruned in its favour
+    let mut state = load_install_state()?;
+    if let Some(versions) = state.retained.get_mut(id) {
+        versions.retain(|v| v.installed_at != chosen.installed_at);
+    }
+    state.retained.retain(|_, versions| !versions.is_empty());
+    save_install_state(&state)?;
+    if let Some(replaced) = replaced {
+        if let Err(e) = retain_version(id, &replaced) {
+            warn!("Failed to keep the replaced version of {}: {}", id, e);
+            remove_path(&replaced)?;
+        }
+    }
+
+    let mut state = load_install_state()?;
+    state.items.insert(id.to_owned(), InstalledItem {
+        title: chosen.title.clone(),
+        time_updated: chosen.time_updated,
+        installed_at: chosen.installed_at,
+        checksum: chosen.checksum.clone(),
+        checksum_version: chosen.checksum_version,
+        dependency: current.as_ref().is_some_and(|c| c.dependency),
+        workshop_children: current.as_ref().map(|c| c.workshop_children.clone()).unwrap_or_default(),
+        rolled_back_from,
+    });
+    save_install_state(&state)?;
+    Ok(chosen)
+}
+
+/// The revision a rollback moves away from, which shouldn't be offered as an update again. Rolling back further keeps
+/// skipping whatever an earlier rollback moved away from. `untracked` is when the replaced version was installed, for
+/// when there is no record of it
+fn get_rolled_back_from(current: Option<&InstalledItem>, untracked: Option<i64>) -> Option<i64> {
+    match current {
+        // imported items have no workshop timestamp, but can be no older than when they were installed
+        Some(current) => current.rolled_back_from.max(Some(current.time_updated.unwrap_or(current.installed_at))),
+        None => untracked,
+    }
+}
+
+/// When an item without an install record was installed, as best as can be told from disk
+fn get_untracked_installed_at(dir: &Path) -> Result<i64> {
+    Ok(dir.metadata()?.created().ok()
+        .map_or(Utc::now().timestamp(), |t| DateTime::<Utc>::from(t).timestamp()))
+}
+
+/// The latest kept version of an item installed at or before `to`, or the latest of all
+fn choose_retained_version(state: &InstallState, id: &str, to: Option<i64>) -> Result<RetainedVersion> {
+    state.retained.get(id).into_iter()
+        .flatten()
+        .filter(|v| to.is_none_or(|to| v.installed_at <= to))
+        .max_by_key(|v| v.installed_at)
+        .cloned()
+        .ok_or(Error::InvalidInput(format!("no kept version of {} to roll back to", id)))
+}
+
+/// Take a snapshot of every installed item along with its install record and the load order.
+/// File contents are shared with the collection by copy-on-write or hard links where the filesystem allows,
+/// and copied otherwise. Returns the snapshot and the number of bytes that had to be copied
+pub fn create_snapshot(name: impl AsRef<str>) -> Result<(Snapshot, u64)> {
+    let name = name.as_ref();
+    check_snapshot_name(name)?;
+    let snapshot_dir = get_snapshot_dir(name)?;
+    if snapshot_dir.symlink_metadata().is_ok() {
+        return Err(Error::InvalidInput(format!("snapshot {} already exists", name)));
+    }
+
+    let collection_dir = get_collection_dir()?;
+    let state = load_install_state()?;
+    let ids = get_local_descriptors()?.into_keys().sorted().collect_vec();
+    let checksums = calculate_local_checksums(&ids.iter()
+        .map(|id| (id.clone(), ChecksumVersion::LATEST))
+        .collect_vec(), true)?;
+
+    // built under another name, so an interrupted snapshot is never mistaken for a complete one
+    let partial_dir = get_snapshots_dir()?.join(format!(".{}.partial", name));
+    remove_path(&partial_dir)?;
+    std::fs::create_dir_all(&partial_dir)?;
+    let built = (|| -> Result<(Snapshot, u64)> {
+        let mut snapshot = Snapshot {
+            name: name.to_owned(),
+            created_at: Utc::now().timestamp(),
+            items: BTreeMap::new(),
+            load_order: state.load_order.clone(),
+        };
+        let mut copied = 0;
+        for (id, checksum) in ids.iter().zip(checksums) {
+            let Some(checksum) = checksum else {
+                continue;
+            };
+            let item_dir = collection_dir.join(id);
+            trace!("Snapshotting {} to {}", item_dir.display(), partial_dir.join(id).display());
+            // installed items are only ever replaced whole, never edited in place, so hard links are safe to share.
+            // Anything that does edit them is caught by the checksum check before a restore
+            copied += clone_tree(&item_dir, &partial_dir.join(id), &[ShareMethod::Reflink, ShareMethod::HardLink])?;
+            snapshot.items.insert(id.clone(), SnapshotItem {
+                checksum,
+                checksum_version: ChecksumVersion::LATEST,
+                size: get_dir_size(&item_dir)?,
+                installed: state.items.get(id).cloned(),
+            });
+        }
+        std::fs::write(partial_dir.join(SNAPSHOT_FILE), serde_json::to_string_pretty(&snapshot)?)?;
+        std::fs::rename(&partial_dir, &snapshot_dir)?;
+        Ok((snapshot, copied))
+    })();
+    if built.is_err() {
+        if let Err(e) = remove_path(&partial_dir) {
+            warn!("Failed to clean up {}: {}", partial_dir.display(), e);
+        }
+    }
+    built
+}
+
+/// All snapshots of the collection, oldest first
+pub fn list_snapshots() -> Result<Vec<Snapshot>> {
+    let snapshots_dir = get_snapshots_dir()?;
+    if !snapshots_dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut snapshots = vec![];
+    for entry in std::fs::read_dir(snapshots_dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let snapshot_file = entry.path().join(SNAPSHOT_FILE);
+        match std::fs::read_to_string(&snapshot_file).map_err(Error::from)
+            .and_then(|s| Ok(serde_json::from_str::<Snapshot>(&s)?)) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(e) => warn!("Ignoring unreadable snapshot {}: {}", snapshot_file.display(), e),
+        }
+    }
+    snapshots.sort_by_key(|s| s.created_at);
+    Ok(snapshots)
+}
+
+pub fn load_snapshot(name: impl AsRef<str>) -> Result<Snapshot> {
+    let name = name.as_ref();
+    check_snapshot_name(name)?;
+    let snapshot_file = get_snapshot_dir(name)?.join(SNAPSHOT_FILE);
+    if !snapshot_file.is_file() {
+        return Err(Error::InvalidInput(format!("no snapshot named {}", name)));
+    }
+    Ok(serde_json::from_str(&std::fs::read_to_string(snapshot_file)?)?)
+}
+
+/// What restoring a snapshot would change
+pub struct SnapshotRestorePlan {
+    /// Snapshotted items that are missing from the collection or differ from their installed version
+    pub changed: Vec<String>,
+    /// Installed items the snapshot doesn't have
+    pub removed: Vec<String>,
+    /// Snapshotted items whose copies no longer match the checksums taken with them, which are left as installed
+    pub damaged: Vec<String>,
+    /// Whether the install records or load order differ from the snapshot's
+    pub records_changed: bool,
+}
+
+impl SnapshotRestorePlan {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty() && !self.records_changed
+    }
+}
+
+/// Work out what restoring a snapshot would change, without changing anything
+pub fn plan_snapshot_restore(snapshot: &Snapshot) -> Result<SnapshotRestorePlan> {
+    let snapshot_dir = get_snapshot_dir(&snapshot.name)?;
+    let items = snapshot.items.iter()
+        .map(|(id, item)| (id.clone(), item.checksum_version))
+        .collect_vec();
+    // read every byte, as the copies share modification times with the installed files they were taken from
+    let uncached = FileDigestCache::uncached();
+    let copies = parallel_map(&items, |(id, version)| calculate_checksum(snapshot_dir.join(id), *version, &uncached));
+    let mut damaged = vec![];
+    for ((id, _), checksum) in items.iter().zip(copies) {
+        match checksum {
+            Ok(checksum) if checksum == snapshot.items[id].checksum => {},
+            Ok(_) => damaged.push(id.clone()),
+            Err(e) => {
+                warn!("Copy of {} in snapshot {} could not be read: {}", id, snapshot.name, e);
+                damaged.push(id.clone());
+            },
+        }
+    }
+    let items = items.into_iter().filter(|(id, _)| !damaged.contains(id)).collect_vec();
+
+    let installed = calculate_local_checksums(&items, false)?;
+    let changed = items.iter().zip(installed)
+        .filter(|((id, _), checksum)| checksum.as_ref() != Some(&snapshot.items[id].checksum))
+        .map(|((id, _), _)| id.clone())
+        .collect();
+    let removed = get_local_descriptors()?.into_keys()
+        .filter(|id| !snapshot.items.contains_key(id))
+        .sorted()
+        .collect();
+    let state = load_install_state()?;
+    let records_changed = state.load_order != snapshot.load_order
+        || items.iter().any(|(id, _)| state.items.get(id) != snapshot.items[id].installed.as_ref());
+    Ok(SnapshotRestorePlan {
+        changed,
+        removed,
+        damaged,
+        records_changed,
+    })
+}
+
+/// Put the collection back as it was when a snapshot was taken, as planned by [`plan_snapshot_restore`].
+/// Replaced and removed items are kept as previous versions, like any other replaced item
+pub fn restore_snapshot(snapshot: &Snapshot, plan: &SnapshotRestorePlan) -> Result<()> {
+    let snapshot_dir = get_snapshot_dir(&snapshot.name)?;
+    let collection_dir = get_collection_dir()?;
+    let staging_dir = get_staging_dir()?;
+    std::fs::create_dir_all(&staging_dir)?;
+    let keep_replaced = |id: &str, replaced: &Path| -> Result<()> {
+        if let Err(e) = retain_version(id, replaced) {
+            warn!("Failed to keep the replaced version of {}: {}", id, e);
+            remove_path(replaced)?;
+        }
+        Ok(())
+    };
+
+    for id in plan.changed.iter() {
+        let staged_dir = staging_dir.join(id);
+        remove_path(&staged_dir)?;
+        trace!("Restoring {} from snapshot {}", id, snapshot.name);
+        let replaced = match clone_tree(&snapshot_dir.join(id), &staged_dir, &[ShareMethod::Reflink, ShareMethod::HardLink])
+            .and_then(|_| swap_into_place(&staged_dir, &collection_dir.join(id))) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                if let Err(cleanup) = remove_path(&staged_dir) {
+                    warn!("Failed to clean up {}: {}", staged_dir.display(), cleanup);
+                }
+                return Err(e);
+            },
+        };
+        if let Some(replaced) = replaced {
+            keep_replaced(id, &replaced)?;
+        }
+        // record each item as it goes, so an interrupted restore leaves the install state matching what's on disk
+        let mut state = load_install_state()?;
+        match snapshot.items[id].installed.clone() {
+            Some(item) => state.items.insert(id.clone(), item),
+            None => state.items.remove(id),
+        };
+        save_install_state(&state)?;
+    }
+
+    for id in plan.removed.iter() {
+        let previous = staging_dir.join(id).with_extension("previous");
+        remove_path(&previous)?;
+        trace!("Removing {} as it isn't in snapshot {}", id, snapshot.name);
+        std::fs::rename(collection_dir.join(id), &previous)?;
+        keep_replaced(id, &previous)?;
+        let mut state = load_install_state()?;
+        state.items.remove(id);
+        save_install_state(&state)?;
+    }
+
+    // items that already matched may still have had their records changed since
+    let mut state = load_install_state()?;
+    for (id, item) in snapshot.items.iter().filter(|(id, _)| !plan.damaged.contains(id)) {
+        match item.installed.clone() {
+            Some(installed) => state.items.insert(id.clone(), installed),
+            None => state.items.remove(id),
+        };
+    }
+    state.load_order = snapshot.load_order.clone();
+    save_install_state(&state)
+}
+
+pub fn delete_snapshot(name: impl AsRef<str>) -> Result<Snapshot> {
+    let snapshot = load_snapshot(name)?;
+    remove_path(&get_snapshot_dir(&snapshot.name)?)?;
+    Ok(snapshot)
+}
+
+/// Snapshot names become folder names, so keep them to something safe on every platform
+fn check_snapshot_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) {
+        return Err(Error::InvalidInput(format!("'{}' is not a valid snapshot name, use letters, digits, '-', '_' and '.'", name)));
+    }
+    Ok(())
+}
+
+/// Ways of sharing a file's contents rather than copying it
+#[derive(Clone, Copy)]
+enum ShareMethod {
+    Reflink,
+    HardLink,
+}
+
+/// Recreate the files under `source` at `dest`, sharing their contents rather than copying where the filesystem allows.
+/// Each file is shared by the first of `methods` that works, or failing all of them copied. Returns the number of bytes copied
+fn clone_tree(source: &Path, dest: &Path, methods: &[ShareMethod]) -> Result<u64> {
+    clone_tree_with(source, dest, methods, share_file)
+}
+
+fn share_file(method: ShareMethod, source: &Path, dest: &Path) -> std::io::Result<()> {
+    match method {
+        ShareMethod::Reflink => reflink_copy::reflink(source, dest),
+        ShareMethod::HardLink => std::fs::hard_link(source, dest),
+    }
+}
+
+/// `clone_tree`, sharing files with `share`. Once `dest` turns out to be on another filesystem than `source`,
+/// nothing else can be shared either, so the rest is copied without trying
+fn clone_tree_with(
+    source: &Path,
+    dest: &Path,
+    mut methods: &[ShareMethod],
+    share: impl Fn(ShareMethod, &Path, &Path) -> std::io::Result<()>,
+) -> Result<u64> {
+    let mut copied = 0;
+    // follow links, so an item linked into the collection is cloned as the files it links to
+    for entry in WalkDir::new(source).follow_links(true).sort_by_file_name() {
+        let entry = entry.map_err(std::io::Error::from)?;
+        let target = dest.join(entry.path().strip_prefix(source).map_err(|e| Error::Internal(e.to_string()))?);
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(&target)?;
+            continue;
+        }
+        let metadata = entry.metadata().map_err(std::io::Error::from)?;
+        // sharing a linked file would share the link rather than its contents
+        let file_methods = if entry.path_is_symlink() { &[] } else { methods };
+        let mut shared = None;
+        for method in file_methods.iter().copied() {
+            match share(method, entry.path(), &target) {
+                Ok(()) => {
+                    shared = Some(method);
+                    break;
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                    warn!("{} and {} are on different filesystems, copying instead", source.display(), dest.display());
+                    methods = &[];
+                    break;
+                },
+                Err(e) => trace!("Could not share {}: {}", entry.path().display(), e),
             }
         }
-        fs_extra::copy_items(&vec![source_dir], &dest_dir, &CopyOptions::new().copy_inside(true))?;
-        Ok(())
-    } else {
-        Err(std::io::Error::new(
-            std::io::ErrorKind::NotFound, 
-            format!("Directory {} not found", source_dir.display())).into())
+        match shared {
+            // the same file, so there is nothing more to do
+            Some(ShareMethod::HardLink) => continue,
+            Some(ShareMethod::Reflink) => {},
+            None => {
+                std::fs::copy(entry.path(), &target)?;
+                copied += metadata.len();
+            },
+        }
+        // keep the modification time so the digest cache still recognises the contents, though only as an optimisation
+        let preserved = metadata.modified()
+            .and_then(|mtime| std::fs::File::options().write(true).open(&target)?.set_modified(mtime));
+        if let Err(e) = preserved {
+            trace!("Could not keep modification time of {}: {}", target.display(), e);
+        }
+    }
+    Ok(copied)
+}
+
+/// Total size of the files under a directory
+fn get_dir_size(dir: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in WalkDir::new(dir) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        if entry.file_type().is_file() {
+            size += entry.metadata().map_err(std::io::Error::from)?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Remove a file, directory or symlink, if there is anything there. Symlinks are removed, not followed
+fn remove_path(path: &Path) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(std::fs::remove_dir_all(path)?),
+        // windows tells directory symlinks apart from file ones, and only removes them as directories
+        #[cfg(windows)]
+        Ok(metadata) if std::os::windows::fs::FileTypeExt::is_symlink_dir(&metadata.file_type()) => Ok(std::fs::remove_dir(path)?),
+        Ok(_) => Ok(std::fs::remove_file(path)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove an installed item from the collection, the steamcmd download cache and everywhere in the install state
+pub fn remove_installed_item(workshop_item_id: impl AsRef<str>) -> Result<()> {
+    let stellaris_appid = "281990";
+    let id = workshop_item_id.as_ref();
+
+    let dirs = [
+        get_collection_dir()?.join(id),
+        get_steamcmd_dir()?.join(format!("steamapps/workshop/content/{}/{}", stellaris_appid, id)),
+        get_steamcmd_dir()?.join(format!("steamapps/workshop/downloads/{}/{}", stellaris_appid, id)),
+    ];
+    for dir in dirs {
+        if dir.is_dir() {
+            trace!("Removing {}", dir.display());
+            std::fs::remove_dir_all(dir)?;
+        }
+    }
+
+    let versions_dir = get_versions_dir()?.join(id);
+    if versions_dir.is_dir() {
+        trace!("Removing {}", versions_dir.display());
+        std::fs::remove_dir_all(versions_dir)?;
+    }
+
+    let mut state = load_install_state()?;
+    let recorded = state.items.remove(id).is_some();
+    let retained = state.retained.remove(id).is_some();
+    let held = state.held.remove(id);
+    let load_order_len = state.load_order.len();
+    state.load_order.retain(|i| i != id);
+    if recorded || retained || held || state.load_order.len() != load_order_len {
+        save_install_state(&state)?;
+    }
+    Ok(())
+}
+
+/// For each installed item, the other installed items it depends on.
+/// An item depends on its Workshop children, and on any item named in the `dependencies` of its descriptor.
+pub fn get_installed_dependencies(
+    descriptors: &HashMap<String, Descriptor>,
+    workshop_children: &HashMap<String, Vec<String>>,
+) -> HashMap<String, HashSet<String>> {
+    let ids_by_name = descriptors.iter()
+        .map(|(id, desc)| (desc.name.as_str(), id))
+        .collect::<HashMap<_, _>>();
+
+    descriptors.iter().map(|(id, desc)| {
+        let named_dependencies = desc.dependencies.iter()
+            .flatten()
+            .filter_map(|name| ids_by_name.get(name.as_str()).map(|id| (*id).clone()));
+        let dependencies = workshop_children.get(id).into_iter()
+            .flatten()
+            .cloned()
+            .chain(named_dependencies)
+            .filter(|dep| dep != id && descriptors.contains_key(dep))
+            .collect();
+        (id.clone(), dependencies)
+    }).collect()
+}
+
+/// Dependencies of the items being removed that nothing else left installed needs, following their own dependencies
+/// in turn. Returns those recorded as installed as dependencies, which can be removed with them, and separately those
+/// that were requested themselves or are held, which are kept
+pub fn find_orphans(
+    dependencies: &HashMap<String, HashSet<String>>,
+    removing: &HashSet<String>,
+    state: &InstallState,
+) -> (Vec<String>, Vec<String>) {
+    let mut removing = removing.clone();
+    let mut orphans = vec![];
+    let mut kept = BTreeSet::new();
+    let is_needed = |id: &str, removing: &HashSet<String>| dependencies.iter()
+        .any(|(other, deps)| !removing.contains(other) && deps.contains(id));
+    loop {
+        let candidates = removing.iter()
+            .flat_map(|id| dependencies.get(id).into_iter().flatten())
+            .filter(|dep| !removing.contains(*dep) && !kept.contains(*dep) && !is_needed(dep, &removing))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        let (new_orphans, new_kept): (Vec<_>, Vec<_>) = candidates.into_iter()
+            .partition(|id| state.items.get(id).is_some_and(|i| i.dependency) && !state.held.contains(id));
+        if new_orphans.is_empty() && new_kept.is_empty() {
+            break;
+        }
+        removing.extend(new_orphans.iter().cloned());
+        orphans.extend(new_orphans);
+        kept.extend(new_kept);
     }
+    (orphans, kept.into_iter().collect())
+}
+
+/// Workshop children of each item the Steam Web API returned details for
+pub fn get_workshop_children(workshop_details: &HashMap<String, GetPublishedFileDetailsResponseItem>) -> HashMap<String, Vec<String>> {
+    workshop_details.iter().filter_map(|(id, response)| match response {
+        GetPublishedFileDetailsResponseItem::FileDetails(fd) => Some((id.clone(), fd.children.iter()
+            .flatten()
+            .map(|c| c.publishedfileid.clone())
+            .collect())),
+        GetPublishedFileDetailsResponseItem::MissingItem { .. } => None,
+    }).collect()
+}
+
+/// Workshop children of each installed item as recorded when it was installed
+pub fn get_recorded_workshop_children(state: &InstallState) -> HashMap<String, Vec<String>> {
+    state.items.iter()
+        .map(|(id, item)| (id.clone(), item.workshop_children.clone()))
+        .collect()
 }
 
 /// SteamCMD expects downloaded content to persist in its own directory so it can do dependency checking etc.
-/// We copy the workshop files out to our own directory. You can manually purge to save disk space once all downloads are complete.
-pub fn purge_download_cache() -> Result<()> {
+/// Depending on the install strategy, items are copied, moved or linked out of it into the collection.
+/// Clear steamcmd's workshop cache, except for the items the collection links to. Returns the ids of those kept
+pub fn purge_download_cache() -> Result<Vec<String>> {
     let stellaris_appid = "281990";
+    let collection_dir = get_collection_dir()?;
+    let mut kept = vec![];
     let mut content_dir = get_steamcmd_dir()?;
     content_dir.push(format!("steamapps/workshop/content/{}", stellaris_appid));
     if content_dir.is_dir() {
-        std::fs::remove_dir_all(content_dir)?;
+        for entry in std::fs::read_dir(&content_dir)? {
+            let entry = entry?;
+            let id = entry.file_name().to_string_lossy().to_string();
+            if collection_dir.join(&id).is_symlink() {
+                kept.push(id);
+            } else {
+                remove_path(&entry.path())?;
+            }
+        }
+        if kept.is_empty() {
+            std::fs::remove_dir(content_dir)?;
+        }
     }
     let mut downloads_dir = get_steamcmd_dir()?;
     downloads_dir.push(format!("steamapps/workshop/downloads/{}", stellaris_appid));
     if downloads_dir.is_dir() {
         std::fs::remove_dir_all(downloads_dir)?;
     }
-    Ok(())
+    kept.sort();
+    Ok(kept)
 }
 
 pub fn get_config_or_default() -> Result<Config> {
@@ -160,6 +1586,11 @@ pub fn get_config_or_default() -> Result<Config> {
         let default = Config {
             collection_path: "mods".to_owned(),
             steam_webapi_key: String::new(),
+            steamcmd_installer_url: None,
+            stellaris_user_dir: None,
+            retained_versions: None,
+            retained_versions_max_mb: None,
+            install_strategy: None,
         };
         warn!("Config file does not exist, creating default at {}", config_file.display());
         std::fs::write(&config_file, toml::to_string_pretty(&default)?)?;
@@ -206,7 +1637,7 @@ pub async fn fetch_workshop_details_with_dependencies(webapi_client: &SteamWebAp
             child_ids.extend(new_child_ids);
 
             // append new file details into cache
-            cached_file_details.extend(new_file_details.into_iter());
+            cached_file_details.extend(new_file_details);
         }
 
         // repeat by fetching new child dependencies
@@ -215,6 +1646,44 @@ pub async fn fetch_workshop_details_with_dependencies(webapi_client: &SteamWebAp
     Ok(cached_file_details)
 }
 
+/// Given a list of workshop file ids, replace any collections with their members, in collection order.
+/// Nested collections are expanded too, and duplicate ids are dropped.
+pub async fn expand_collections(webapi_client: &SteamWebApiClient, file_ids: impl Iterator<Item = String>) -> Result<Vec<String>> {
+    let mut expanded = vec![];
+    let mut seen = HashSet::new();
+    let mut pending = file_ids.collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let mut collection_details = HashMap::new();
+        for chunk in &pending.iter().chunks(5) {
+            collection_details.extend(webapi_client.get_collection_details(chunk).await?);
+        }
+
+        let mut nested = vec![];
+        for id in pending {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            match collection_details.get(&id).and_then(|d| d.children.as_ref()) {
+                Some(children) if !children.is_empty() => {
+                    info!("Expanding collection {} with {} members", id, children.len());
+                    for child in children.iter().sorted_by_key(|c| c.sortorder) {
+                        if child.filetype == COLLECTION_FILETYPE {
+                            nested.push(child.publishedfileid.clone());
+                        } else if seen.insert(child.publishedfileid.clone()) {
+                            expanded.push(child.publishedfileid.clone());
+                        }
+                    }
+                },
+                _ => expanded.push(id),
+            }
+        }
+
+        // nested collections are expanded in turn
+        pending = nested;
+    }
+    Ok(expanded)
+}
+
 fn get_root_dir() -> Result<PathBuf> {
     let current_exe = dunce::canonicalize(std::env::current_exe()?)?;
     let dir = current_exe.parent().expect("exe shouldn't be a root path");
@@ -222,23 +1691,55 @@ fn get_root_dir() -> Result<PathBuf> {
     Ok(dir.into())
 }
 
+#[allow(dead_code)]
 fn get_irony_dir() -> Result<PathBuf> {
     Ok(get_root_dir()?.join("irony"))
 }
 
+#[allow(dead_code)]
 fn get_irony_exe() -> Result<PathBuf> {
     let mut ret = get_irony_dir()?;
     ret.push("IronyModManager.exe");
     Ok(ret)
 }
 
+/// The Stellaris user directory, holding the launcher database and the game's `mod/` folder
+fn get_stellaris_user_dir() -> Result<PathBuf> {
+    if let Some(dir) = get_config_or_default()?.stellaris_user_dir {
+        return Ok(dir.into());
+    }
+    let home = std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .map(PathBuf::from)
+        .ok_or(Error::InvalidInput("can't find the home directory, set stellaris_user_dir in the config file".to_owned()))?;
+    let parent = if cfg!(all(unix, not(target_os = "macos"))) {
+        std::env::var_os("XDG_DATA_HOME").map_or(home.join(".local/share"), PathBuf::from)
+    } else {
+        home.join("Documents")
+    };
+    Ok(parent.join("Paradox Interactive").join("Stellaris"))
+}
+
+/// Descriptors the game reads mods from
+fn get_stellaris_mod_dir() -> Result<PathBuf> {
+    Ok(get_stellaris_user_dir()?.join("mod"))
+}
+
+fn get_launcher_db_file() -> Result<PathBuf> {
+    Ok(get_stellaris_user_dir()?.join("launcher-v2.sqlite"))
+}
+
 fn get_steamcmd_dir() -> Result<PathBuf> {
     Ok(get_root_dir()?.join("steamcmd"))
 }
 
 fn get_steamcmd_exe() -> Result<PathBuf> {
     let mut ret = get_steamcmd_dir()?;
-    ret.push("steamcmd.exe");
+    if cfg!(windows) {
+        ret.push("steamcmd.exe");
+    } else {
+        // the wrapper script sets up LD_LIBRARY_PATH and handles self-updates
+        ret.push("steamcmd.sh");
+    }
     Ok(ret)
 }
 
@@ -256,6 +1757,42 @@ fn get_collection_dir() -> Result<PathBuf> {
     Ok(ret)
 }
 
+/// Ironworks' own bookkeeping lives in a hidden directory inside the collection, so it travels with it
+fn get_state_dir() -> Result<PathBuf> {
+    Ok(get_collection_dir()?.join(".ironworks"))
+}
+
+fn get_install_state_file() -> Result<PathBuf> {
+    Ok(get_state_dir()?.join("state.json"))
+}
+
+/// Where new copies of items are prepared before being swapped into the collection
+fn get_staging_dir() -> Result<PathBuf> {
+    Ok(get_state_dir()?.join("staging"))
+}
+
+/// Where replaced versions of items are kept for rolling back
+fn get_versions_dir() -> Result<PathBuf> {
+    Ok(get_state_dir()?.join("versions"))
+}
+
+fn get_version_dir(id: &str, installed_at: i64) -> Result<PathBuf> {
+    Ok(get_versions_dir()?.join(id).join(installed_at.to_string()))
+}
+
+/// Where snapshots of the whole collection are kept
+fn get_snapshots_dir() -> Result<PathBuf> {
+    Ok(get_state_dir()?.join("snapshots"))
+}
+
+fn get_snapshot_dir(name: &str) -> Result<PathBuf> {
+    Ok(get_snapshots_dir()?.join(name))
+}
+
+fn get_digest_cache_file() -> Result<PathBuf> {
+    Ok(get_state_dir()?.join("digest_cache.json"))
+}
+
 fn ensure_init() -> Result<()> {
     if !get_steamcmd_exe()?.is_file() {
         Err(Error::NotInitialised())
@@ -265,8 +1802,30 @@ fn ensure_init() -> Result<()> {
 }
 
 fn download_and_unzip(url: impl AsRef<str>, unzip_dest: impl AsRef<Path>) -> Result<()> {
+    let buf = download_to_memory(url)?;
+
+    // unzip from in-memory buffer
+    let mut archive = ZipArchive::new(Cursor::new(buf))?;
+    archive.extract(unzip_dest.as_ref())?;
+    trace!("Extracted to {}", unzip_dest.as_ref().display());
+    Ok(())
+}
+
+fn download_and_untar_gz(url: impl AsRef<str>, untar_dest: impl AsRef<Path>) -> Result<()> {
+    let buf = download_to_memory(url)?;
+
+    // untar from in-memory buffer, keeping file modes so the steamcmd binaries stay executable
+    let mut archive = tar::Archive::new(GzDecoder::new(Cursor::new(buf)));
+    archive.set_preserve_permissions(true);
+    archive.unpack(untar_dest.as_ref())?;
+    trace!("Extracted to {}", untar_dest.as_ref().display());
+    Ok(())
+}
+
+fn download_to_memory(url: impl AsRef<str>) -> Result<Vec<u8>> {
     let mut curl = Easy::new();
     curl.follow_location(true)?;
+    curl.fail_on_error(true)?;
     curl.url(url.as_ref())?;
     let mut buf = Vec::new();
     {
@@ -279,41 +1838,58 @@ fn download_and_unzip(url: impl AsRef<str>, unzip_dest: impl AsRef<Path>) -> Res
         transfer.perform()?;
     }
     trace!("Download complete, downloaded {} bytes", buf.len());
-
-    // unzip from in-memory buffer
-    let mut archive = ZipArchive::new(Cursor::new(buf))?;
-    archive.extract(unzip_dest.as_ref())?;
-    trace!("Extracted to {}", unzip_dest.as_ref().display());
-    Ok(())
+    Ok(buf)
 }
 
 /// Calculate combined checksum of directory structure.
 /// Algorithm is `b64(SHA256(concat(map(SHA256, [file_contents]))))`
-fn calculate_checksum(dir: impl AsRef<Path>) -> Result<String> {
+///
+/// Kept to verify old manifests. File paths aren't included, and unreadable files are skipped
+fn calculate_checksum_v1(dir: impl AsRef<Path>, cache: &FileDigestCache) -> Result<String> {
+    let dir = dir.as_ref();
     let files = WalkDir::new(dir).sort_by_file_name();
     let all_digests = files.into_iter().filter_map(|e| {
         if let Ok(e) = e {
             if e.file_type().is_file() {
-                match std::fs::File::open(e.path()) {
-                    Ok(file) => {
-                        match sha256digest(file) {
-                            Ok(digest) => return Some(digest),
-                            Err(err) => warn!("error when calculating sha256digest for file {}: {}. Skipping this file", e.path().display(), err),
-                        }
-                    }
-                    Err(err) => warn!("error opening file to calculate sha256digest for file {}: {}. Skipping this file", e.path().display(), err),
+                let digest = e.metadata().map_err(|e| Error::from(std::io::Error::from(e)))
+                    .and_then(|metadata| cache.digest(e.path(), &metadata));
+                match digest {
+                    Ok(digest) => return Some(digest),
+                    Err(err) => warn!("error when calculating sha256digest for file {}: {}. Skipping this file", e.path().display(), err),
                 }
             }
         }
         None
     }).fold(vec![], |mut acc, x| {
-        acc.extend_from_slice(x.as_ref());
+        acc.extend_from_slice(&x);
         acc
     });
     let overall_digest = sha256digest(all_digests.as_slice())?;
     Ok(base64::prelude::BASE64_STANDARD.encode(overall_digest.as_ref()))
 }
 
+/// Calculate combined checksum of directory structure, including file paths.
+/// Algorithm is `b64(SHA256(concat(map(path + '\0' + SHA256(file_contents), [files]))))`
+/// with files sorted by their `/` separated path relative to `dir`
+fn calculate_checksum_v2(dir: impl AsRef<Path>, cache: &FileDigestCache) -> Result<String> {
+    let mut context = digest::Context::new(&digest::SHA256);
+    for file in calculate_file_digests(dir, cache)? {
+        context.update(file.path.as_bytes());
+        context.update(&[0]);
+        let digest = base64::prelude::BASE64_STANDARD.decode(&file.sha256)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        context.update(&digest);
+    }
+    Ok(base64::prelude::BASE64_STANDARD.encode(context.finish().as_ref()))
+}
+
+fn calculate_checksum(dir: impl AsRef<Path>, version: ChecksumVersion, cache: &FileDigestCache) -> Result<String> {
+    match version {
+        ChecksumVersion::V1 => calculate_checksum_v1(dir, cache),
+        ChecksumVersion::V2 => calculate_checksum_v2(dir, cache),
+    }
+}
+
 fn sha256digest(mut reader: impl Read) -> Result<digest::Digest> {
     let mut context = digest::Context::new(&digest::SHA256);
     let mut buf = [0;2048];
@@ -327,6 +1903,21 @@ fn sha256digest(mut reader: impl Read) -> Result<digest::Digest> {
     Ok(context.finish())
 }
 
+/// Clean up a raw line of steamcmd output, stripping ANSI escapes and surrounding whitespace.
+/// Returns `None` if nothing is left.
+fn clean_output_line(buf: impl AsRef<[u8]>) -> Option<String> {
+    let stripped = strip_ansi_escapes::strip(buf);
+    let line = String::from_utf8_lossy(&stripped);
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_owned())
+    }
+}
+
+/// Worker process backed by a Windows pseudoconsole
+#[cfg(windows)]
 pub struct WorkerProcess {
     output: Option<mpsc::Receiver<String>>,
     proc: conpty::Process,
@@ -334,6 +1925,7 @@ pub struct WorkerProcess {
     _read_interrupt: mpsc::Sender<()>,
 }
 
+#[cfg(windows)]
 impl WorkerProcess {
     pub fn spawn<I, S>(args: I) -> Result<WorkerProcess>
     where
@@ -354,10 +1946,15 @@ impl WorkerProcess {
 
         let read_jh = std::thread::spawn::<_, Result<()>>(move || {
             let mut br = BufReader::new(out);
+            let mut interrupted = false;
             loop {
                 let mut buf = String::new();
                 match br.read_line(&mut buf) {
                     Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        // once interrupted, keep going until everything already written has been drained
+                        if interrupted {
+                            break;
+                        }
                         thread::sleep(Duration::from_millis(10))
                     },
                     Err(e) => {
@@ -370,17 +1967,16 @@ impl WorkerProcess {
                     }
                     Ok(_) => {
                         // clean up steamcmd output
-                        let stripped = strip_ansi_escapes::strip(buf);
-                        let line = String::from_utf8_lossy(&stripped);
-                        let trimmed = line.trim().to_owned();
-                        if !trimmed.is_empty() {
-                            let _ = lines_tx.send(trimmed);
+                        if let Some(line) = clean_output_line(buf) {
+                            let _ = lines_tx.send(line);
                         }
                     }
                 }
-                match interrupt_rx.try_recv() {
-                    Err(TryRecvError::Empty) => (),
-                    _ => break,
+                if !interrupted {
+                    match interrupt_rx.try_recv() {
+                        Err(TryRecvError::Empty) => (),
+                        _ => interrupted = true,
+                    }
                 }
             }
             trace!("exiting read_jh");
@@ -411,6 +2007,7 @@ impl WorkerProcess {
     }
 }
 
+#[cfg(windows)]
 impl Drop for WorkerProcess {
     fn drop(&mut self) {
         // try to gracefully exit the read thread
@@ -419,3 +2016,699 @@ impl Drop for WorkerProcess {
         let _ = self.proc.exit(1);
     }
 }
+
+/// Worker process backed by a plain child process with piped stdout and stderr
+#[cfg(unix)]
+pub struct WorkerProcess {
+    output: Option<mpsc::Receiver<String>>,
+    proc: std::process::Child,
+    _read_jhs: Vec<JoinHandle<Result<()>>>,
+}
+
+#[cfg(unix)]
+impl WorkerProcess {
+    pub fn spawn<I, S>(args: I) -> Result<WorkerProcess>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>
+    {
+        let mut args = args.into_iter();
+        let program = args.next()
+            .ok_or(Error::Internal("WorkerProcess spawned with no command".to_owned()))?;
+        let mut command = std::process::Command::new(program.as_ref());
+        command.args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+
+        trace!("spawning WorkerProcess with command {:?}", command);
+        let mut proc = command.spawn()?;
+
+        let (lines_tx, lines_rx) = mpsc::channel();
+        let stdout = proc.stdout.take().expect("stdout should be piped");
+        let stderr = proc.stderr.take().expect("stderr should be piped");
+        let read_jhs = vec![
+            spawn_line_reader(stdout, lines_tx.clone()),
+            spawn_line_reader(stderr, lines_tx),
+        ];
+
+        Ok(WorkerProcess {
+            proc,
+            output: Some(lines_rx),
+            _read_jhs: read_jhs,
+        })
+    }
+
+    pub fn take_output(&mut self) -> mpsc::Receiver<String> {
+        self.output.take().expect("output is none")
+    }
+
+    pub fn wait(&mut self) -> Result<()> {
+        use std::os::unix::process::ExitStatusExt;
+
+        let status = self.proc.wait()?;
+        trace!("proc is done with status {}", status);
+        match (status.code(), status.signal()) {
+            (Some(0), _) => Ok(()),
+            (Some(exit), _) => Err(Error::WorkerExitCode(exit as u32)),
+            (None, Some(signal)) => Err(Error::Internal(format!("worker process terminated by signal {}", signal))),
+            (None, None) => Err(Error::Internal("worker process terminated without exit status".to_owned())),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        // kill is a no-op error if the process has already been reaped;
+        // read threads exit by themselves once the pipes close
+        if let Ok(None) = self.proc.try_wait() {
+            let _ = self.proc.kill();
+            let _ = self.proc.wait();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn spawn_line_reader(reader: impl Read + Send + 'static, lines_tx: mpsc::Sender<String>) -> JoinHandle<Result<()>> {
+    std::thread::spawn::<_, Result<()>>(move || {
+        let mut br = BufReader::new(reader);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            // steamcmd redraws progress with bare carriage returns, so split on those too
+            match read_line_or_cr(&mut br, &mut buf) {
+                Err(e) => {
+                    error!("error in read_jh {}", e);
+                    break;
+                },
+                Ok(0) => {
+                    trace!("read_jh reached EOF");
+                    break;
+                },
+                Ok(_) => {
+                    // clean up steamcmd output
+                    if let Some(line) = clean_output_line(&buf) {
+                        let _ = lines_tx.send(line);
+                    }
+                },
+            }
+        }
+        trace!("exiting read_jh");
+        Ok(())
+    })
+}
+
+/// Like `BufRead::read_until` with `b'\n'`, but also treats a lone `b'\r'` as a line terminator
+#[cfg(unix)]
+fn read_line_or_cr(reader: &mut impl BufRead, buf: &mut Vec<u8>) -> std::io::Result<usize> {
+    let mut read = 0;
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(b) => b,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if available.is_empty() {
+            return Ok(read);
+        }
+        match available.iter().position(|&b| b == b'\n' || b == b'\r') {
+            Some(i) => {
+                buf.extend_from_slice(&available[..=i]);
+                reader.consume(i + 1);
+                return Ok(read + i + 1);
+            },
+            None => {
+                let len = available.len();
+                buf.extend_from_slice(available);
+                reader.consume(len);
+                read += len;
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_download_result_success() {
+        let line = r#"Success. Downloaded item 2880030227 to "/steamcmd/steamapps/workshop/content/281990/2880030227" (1234 bytes)"#;
+        assert_eq!(parse_download_result(line), Some(("2880030227".to_owned(), DownloadResult::Success)));
+    }
+
+    #[test]
+    fn parse_download_result_failure() {
+        let line = "ERROR! Download item 123 failed (Failure).";
+        assert_eq!(parse_download_result(line),
+            Some(("123".to_owned(), DownloadResult::Failed("Download item 123 failed (Failure).".to_owned()))));
+    }
+
+    #[test]
+    fn parse_download_result_ignores_other_output() {
+        assert_eq!(parse_download_result("Logging in user 'anonymous' to Steam Public...OK"), None);
+        assert_eq!(parse_download_result("Downloading item 123 ..."), None);
+        assert_eq!(parse_download_result("Success. Downloaded item abc to \"x\""), None);
+        assert_eq!(parse_download_result("ERROR! Not logged on."), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_line_or_cr_splits_on_both_terminators() {
+        let mut reader = BufReader::with_capacity(4, Cursor::new(b"first\nprogress 10%\rprogress 20%\r\nlast".to_vec()));
+        let mut lines = vec![];
+        loop {
+            let mut buf = vec![];
+            if read_line_or_cr(&mut reader, &mut buf).unwrap() == 0 {
+                break;
+            }
+            lines.push(String::from_utf8(buf).unwrap());
+        }
+        assert_eq!(lines, ["first\n", "progress 10%\r", "progress 20%\r", "\n", "last"]);
+    }
+
+    fn write_files(dir: &Path, files: &[(&str, &str)]) {
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    fn b64_sha256(data: &[u8]) -> String {
+        base64::prelude::BASE64_STANDARD.encode(digest::digest(&digest::SHA256, data))
+    }
+
+    #[test]
+    fn checksum_v2_matches_documented_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("b.txt", "world"), ("a/c.txt", "hello")]);
+
+        let mut expected = vec![];
+        for (path, contents) in [("a/c.txt", "hello"), ("b.txt", "world")] {
+            expected.extend_from_slice(path.as_bytes());
+            expected.push(0);
+            expected.extend_from_slice(digest::digest(&digest::SHA256, contents.as_bytes()).as_ref());
+        }
+        assert_eq!(calculate_checksum_v2(dir.path(), &FileDigestCache::uncached()).unwrap(), b64_sha256(&expected));
+    }
+
+    #[test]
+    fn checksum_v1_matches_documented_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("a.txt", "hello"), ("b.txt", "world")]);
+
+        let mut expected = vec![];
+        for contents in ["hello", "world"] {
+            expected.extend_from_slice(digest::digest(&digest::SHA256, contents.as_bytes()).as_ref());
+        }
+        assert_eq!(calculate_checksum_v1(dir.path(), &FileDigestCache::uncached()).unwrap(), b64_sha256(&expected));
+    }
+
+    #[test]
+    fn checksum_v2_is_sensitive_to_paths() {
+        let cache = FileDigestCache::uncached();
+        let original = tempfile::tempdir().unwrap();
+        write_files(original.path(), &[("a.txt", "hello"), ("b.txt", "world")]);
+        let renamed = tempfile::tempdir().unwrap();
+        write_files(renamed.path(), &[("a.txt", "hello"), ("c.txt", "world")]);
+        let moved = tempfile::tempdir().unwrap();
+        write_files(moved.path(), &[("a.txt", "hello"), ("sub/b.txt", "world")]);
+
+        // v1 only sees the contents, in the same order
+        assert_eq!(calculate_checksum_v1(original.path(), &cache).unwrap(), calculate_checksum_v1(renamed.path(), &cache).unwrap());
+        let v2 = calculate_checksum_v2(original.path(), &cache).unwrap();
+        assert_ne!(v2, calculate_checksum_v2(renamed.path(), &cache).unwrap());
+        assert_ne!(v2, calculate_checksum_v2(moved.path(), &cache).unwrap());
+    }
+
+    #[test]
+    fn checksum_v2_is_independent_of_location() {
+        let cache = FileDigestCache::uncached();
+        let a = tempfile::tempdir().unwrap();
+        write_files(a.path(), &[("x/y.txt", "hello")]);
+        let b = tempfile::tempdir().unwrap();
+        write_files(b.path(), &[("x/y.txt", "hello")]);
+        assert_eq!(calculate_checksum_v2(a.path(), &cache).unwrap(), calculate_checksum_v2(b.path(), &cache).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn checksum_v2_fails_on_unreadable_files() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let cache = FileDigestCache::uncached();
+        let dir = tempfile::tempdir().unwrap();
+        write_files(dir.path(), &[("a.txt", "hello"), ("secret.txt", "world")]);
+        let secret = dir.path().join("secret.txt");
+        std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o000)).unwrap();
+        if std::fs::File::open(&secret).is_ok() {
+            // running with privileges that ignore permissions, so nothing is unreadable
+            return;
+        }
+
+        assert!(calculate_checksum_v2(dir.path(), &cache).is_err());
+        // v1 skips what it can't read
+        let readable = tempfile::tempdir().unwrap();
+        write_files(readable.path(), &[("a.txt", "hello")]);
+        assert_eq!(calculate_checksum_v1(dir.path(), &cache).unwrap(), calculate_checksum_v1(readable.path(), &cache).unwrap());
+    }
+
+    #[test]
+    fn diff_file_digests_reports_each_kind_of_difference() {
+        let file = |path: &str, contents: &str| FileDigest {
+            path: path.to_owned(),
+            size: contents.len() as u64,
+            sha256: b64_sha256(contents.as_bytes()),
+        };
+        let expected = [file("same.txt", "a"), file("changed.txt", "b"), file("removed.txt", "c")];
+        let local = [file("same.txt", "a"), file("changed.txt", "B"), file("added.txt", "d")];
+        let diff = diff_file_digests(&expected, &local);
+        assert_eq!(diff.added, ["added.txt"]);
+        assert_eq!(diff.removed, ["removed.txt"]);
+        assert_eq!(diff.changed, ["changed.txt"]);
+
+        let diff = diff_file_digests(&expected, &expected);
+        assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.changed.is_empty());
+    }
+
+    fn read_file(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn swap_into_place_replaces_and_returns_previous() {
+        let dir = tempfile::tempdir().unwrap();
+        let staged = dir.path().join("staging/123");
+        let dest = dir.path().join("123");
+        write_files(&staged, &[("a.txt", "new")]);
+        write_files(&dest, &[("a.txt", "old")]);
+
+        let previous = swap_into_place(&staged, &dest).unwrap().unwrap();
+        assert_eq!(read_file(&dest.join("a.txt")), "new");
+        assert_eq!(read_file(&previous.join("a.txt")), "old");
+        assert!(!staged.exists());
+
+        // nothing to move aside for a fresh install
+        write_files(&staged, &[("a.txt", "newer")]);
+        std::fs::remove_dir_all(&previous).unwrap();
+        std::fs::remove_dir_all(&dest).unwrap();
+        assert_eq!(swap_into_place(&staged, &dest).unwrap(), None);
+        assert_eq!(read_file(&dest.join("a.txt")), "newer");
+    }
+
+    #[test]
+    fn swap_into_place_restores_previous_when_rename_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        // renaming a staged copy that isn't there fails after the installed version has been moved aside
+        let staged = dir.path().join("staging/123");
+        std::fs::create_dir_all(staged.parent().unwrap()).unwrap();
+        let dest = dir.path().join("123");
+        write_files(&dest, &[("a.txt", "old")]);
+
+        assert!(swap_into_place(&staged, &dest).is_err());
+        assert_eq!(read_file(&dest.join("a.txt")), "old");
+        assert!(!staged.with_extension("previous").exists());
+    }
+
+    #[test]
+    fn swap_into_place_recovers_interrupted_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let staged = dir.path().join("staging/123");
+        let dest = dir.path().join("123");
+        write_files(&staged, &[("a.txt", "new")]);
+        // interrupted after moving the installed version aside, so there's nothing at dest
+        write_files(&staged.with_extension("previous"), &[("a.txt", "old")]);
+
+        let previous = swap_into_place(&staged, &dest).unwrap().unwrap();
+        assert_eq!(read_file(&dest.join("a.txt")), "new");
+        assert_eq!(read_file(&previous.join("a.txt")), "old");
+
+        // interrupted after the new version was in place, so the leftover is stale
+        write_files(&staged, &[("a.txt", "newer")]);
+        write_files(&previous, &[("a.txt", "stale"), ("b.txt", "stale")]);
+        let previous = swap_into_place(&staged, &dest).unwrap().unwrap();
+        assert_eq!(read_file(&dest.join("a.txt")), "newer");
+        assert_eq!(read_file(&previous.join("a.txt")), "new");
+        assert!(!previous.join("b.txt").exists());
+    }
+
+    #[test]
+    fn verify_staged_copy_rejects_incomplete_copies() {
+        let source = tempfile::tempdir().unwrap();
+        write_files(source.path(), &[("a.txt", "hello"), ("sub/b.txt", "world")]);
+        let staged = tempfile::tempdir().unwrap();
+        write_files(staged.path(), &[("a.txt", "hello"), ("sub/b.txt", "world")]);
+        verify_staged_copy(source.path(), staged.path()).unwrap();
+
+        let missing = tempfile::tempdir().unwrap();
+        write_files(missing.path(), &[("a.txt", "hello")]);
+        assert!(verify_staged_copy(source.path(), missing.path()).is_err());
+
+        let truncated = tempfile::tempdir().unwrap();
+        write_files(truncated.path(), &[("a.txt", "hello"), ("sub/b.txt", "wor")]);
+        assert!(verify_staged_copy(source.path(), truncated.path()).is_err());
+    }
+
+    #[test]
+    fn remove_path_removes_directory_links_but_not_their_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        write_files(&target, &[("a.txt", "hello")]);
+        let link = dir.path().join("link");
+        if let Err(e) = symlink_dir(&target, &link) {
+            // e.g. Windows without developer mode
+            eprintln!("skipping, can't create symlinks: {}", e);
+            return;
+        }
+
+        remove_path(&link).unwrap();
+        assert!(link.symlink_metadata().is_err());
+        assert_eq!(read_file(&target.join("a.txt")), "hello");
+
+        // and once its target is gone
+        symlink_dir(&target, &link).unwrap();
+        std::fs::remove_dir_all(&target).unwrap();
+        remove_path(&link).unwrap();
+        assert!(link.symlink_metadata().is_err());
+    }
+
+    #[test]
+    fn clone_tree_copies_across_filesystems() {
+        let source = tempfile::tempdir().unwrap();
+        write_files(source.path(), &[("a.txt", "hello"), ("sub/b.txt", "world")]);
+        let dest = tempfile::tempdir().unwrap();
+        let staged = dest.path().join("123");
+        let attempts = std::cell::Cell::new(0);
+        let cross_device = |_: ShareMethod, _: &Path, _: &Path| {
+            attempts.set(attempts.get() + 1);
+            Err(std::io::Error::from(std::io::ErrorKind::CrossesDevices))
+        };
+
+        let copied = clone_tree_with(source.path(), &staged, &[ShareMethod::HardLink], cross_device).unwrap();
+        assert_eq!(copied, 10);
+        // the first failure is enough to know nothing else will link either
+        assert_eq!(attempts.get(), 1);
+        verify_staged_copy(source.path(), &staged).unwrap();
+        assert_eq!(read_file(&staged.join("sub/b.txt")), "world");
+    }
+
+    /// A kept version of an item, with a folder for it under `versions_dir` if given
+    fn retained(versions_dir: Option<&Path>, id: &str, installed_at: i64, retained_at: i64, size: u64) -> RetainedVersion {
+        if let Some(versions_dir) = versions_dir {
+            write_files(&versions_dir.join(id).join(installed_at.to_string()), &[("a.txt", "kept")]);
+        }
+        RetainedVersion {
+            title: None,
+            time_updated: None,
+            installed_at,
+            checksum: String::new(),
+            checksum_version: ChecksumVersion::LATEST,
+            retained_at,
+            size,
+        }
+    }
+
+    fn retained_installs(state: &InstallState, id: &str) -> Vec<i64> {
+        state.retained.get(id).into_iter().flatten().map(|v| v.installed_at).collect()
+    }
+
+    #[test]
+    fn prune_retained_versions_keeps_the_newest_per_item() {
+        let dir = tempfile::tempdir().unwrap();
+        let versions_dir = dir.path();
+        let mut state = InstallState::default();
+        state.retained.insert("1".to_owned(), vec![
+            retained(Some(versions_dir), "1", 30, 300, 1),
+            retained(Some(versions_dir), "1", 10, 100, 1),
+            retained(Some(versions_dir), "1", 20, 200, 1),
+        ]);
+        state.retained.insert("2".to_owned(), vec![retained(Some(versions_dir), "2", 10, 100, 1)]);
+
+        prune_retained_versions(&mut state, versions_dir, 2, None).unwrap();
+        assert_eq!(retained_installs(&state, "1"), [20, 30]);
+        assert_eq!(retained_installs(&state, "2"), [10]);
+        assert!(!versions_dir.join("1/10").exists());
+        assert!(versions_dir.join("1/20").is_dir() && versions_dir.join("1/30").is_dir());
+
+        // keeping none forgets the item entirely, along with its folder
+        prune_retained_versions(&mut state, versions_dir, 0, None).unwrap();
+        assert!(state.retained.is_empty());
+        assert!(!versions_dir.join("1").exists() && !versions_dir.join("2").exists());
+    }
+
+    #[test]
+    fn prune_retained_versions_drops_the_oldest_overall_to_fit_size_cap() {
+        const MB: u64 = 1024 * 1024;
+        let dir = tempfile::tempdir().unwrap();
+        let versions_dir = dir.path();
+        let mut state = InstallState::default();
+        state.retained.insert("1".to_owned(), vec![
+            retained(Some(versions_dir), "1", 10, 100, 2 * MB),
+            retained(Some(versions_dir), "1", 40, 400, 2 * MB),
+        ]);
+        state.retained.insert("2".to_owned(), vec![
+            retained(Some(versions_dir), "2", 20, 200, 2 * MB),
+            retained(Some(versions_dir), "2", 30, 300, MB),
+        ]);
+
+        prune_retained_versions(&mut state, versions_dir, 5, Some(4)).unwrap();
+        assert_eq!(retained_installs(&state, "1"), [40]);
+        assert_eq!(retained_installs(&state, "2"), [30]);
+        assert!(!versions_dir.join("1/10").exists() && !versions_dir.join("2/20").exists());
+
+        prune_retained_versions(&mut state, versions_dir, 5, Some(0)).unwrap();
+        assert!(state.retained.is_empty());
+    }
+
+    #[test]
+    fn get_rolled_back_from_remembers_earlier_rollbacks() {
+        // v3 is broken, so roll back to v2
+        let v3 = InstalledItem { time_updated: Some(300), installed_at: 310, ..installed(false) };
+        assert_eq!(get_rolled_back_from(Some(&v3), None), Some(300));
+        // then further back to v1, which must still skip v3
+        let v2 = InstalledItem { time_updated: Some(200), installed_at: 210, rolled_back_from: Some(300), ..installed(false) };
+        assert_eq!(get_rolled_back_from(Some(&v2), None), Some(300));
+        // imported items fall back to when they were installed
+        let imported = InstalledItem { time_updated: None, installed_at: 400, rolled_back_from: Some(300), ..installed(false) };
+        assert_eq!(get_rolled_back_from(Some(&imported), None), Some(400));
+        // with no record, go by the replaced copy
+        assert_eq!(get_rolled_back_from(None, Some(500)), Some(500));
+        assert_eq!(get_rolled_back_from(None, None), None);
+    }
+
+    #[test]
+    fn choose_retained_version_picks_latest_at_or_before_to() {
+        let mut state = InstallState::default();
+        state.retained.insert("1".to_owned(), vec![
+            retained(None, "1", 10, 100, 1),
+            retained(None, "1", 30, 300, 1),
+            retained(None, "1", 20, 200, 1),
+        ]);
+        assert_eq!(choose_retained_version(&state, "1", None).unwrap().installed_at, 30);
+        assert_eq!(choose_retained_version(&state, "1", Some(29)).unwrap().installed_at, 20);
+        assert_eq!(choose_retained_version(&state, "1", Some(20)).unwrap().installed_at, 20);
+        assert!(matches!(choose_retained_version(&state, "1", Some(9)), Err(Error::InvalidInput(_))));
+        assert!(matches!(choose_retained_version(&state, "2", None), Err(Error::InvalidInput(_))));
+    }
+
+    /// Tables of the Paradox Launcher's `launcher-v2.sqlite` that playsets are read from and written to
+    const LAUNCHER_SCHEMA: &str = "
+        CREATE TABLE `mods` (`id` char(36) NOT NULL, `pdxId` VARCHAR(255) UNIQUE, `steamId` VARCHAR(255) UNIQUE,
+            `gameRegistryId` TEXT, `name` VARCHAR(255), `displayName` VARCHAR(255), `thumbnailUrl` TEXT,
+            `thumbnailPath` TEXT, `version` VARCHAR(255), `tags` JSON, `requiredVersion` VARCHAR(255),
+            `arch` VARCHAR(255), `os` VARCHAR(255), `repositoryPath` TEXT, `dirPath` TEXT, `archivePath` TEXT,
+            `status` VARCHAR(255) NOT NULL, `source` VARCHAR(255) NOT NULL, `timeUpdated` BIGINT, `isNew` TINYINT(1),
+            `createdOn` BIGINT, `cause` TEXT, `subscribedOn` BIGINT, PRIMARY KEY (`id`));
+        CREATE TABLE `playsets` (`id` char(36) NOT NULL, `name` VARCHAR(255) NOT NULL, `isActive` TINYINT(1),
+            `loadOrder` VARCHAR(255), `pdxId` VARCHAR(255) UNIQUE, `pdxUserId` VARCHAR(255),
+            `createdOn` DATETIME NOT NULL, `updatedOn` DATETIME, `syncedOn` DATETIME, `lastServerChecksum` TEXT,
+            `isRemoved` TINYINT(1) NOT NULL DEFAULT 0, `hasNotApprovedChanges` TINYINT(1) NOT NULL DEFAULT 0,
+            `syncState` VARCHAR(255), PRIMARY KEY (`id`));
+        CREATE TABLE `playsets_mods` (
+            `playsetId` char(36) NOT NULL REFERENCES `playsets` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+            `modId` char(36) NOT NULL REFERENCES `mods` (`id`) ON DELETE CASCADE ON UPDATE CASCADE,
+            `enabled` TINYINT(1) DEFAULT 1, `position` INTEGER, PRIMARY KEY (`playsetId`, `modId`));";
+
+    fn launcher_db() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA foreign_keys = ON;").unwrap();
+        conn.execute_batch(LAUNCHER_SCHEMA).unwrap();
+        conn
+    }
+
+    #[test]
+    fn read_playset_finds_workshop_mods_in_order() {
+        let conn = launcher_db();
+        conn.execute_batch("
+            INSERT INTO playsets (id, name, isActive, loadOrder, createdOn) VALUES ('p', 'Main', 1, 'custom', '2024-01-01 00:00:00.000 +00:00');
+            INSERT INTO mods (id, steamId, gameRegistryId, displayName, status, source) VALUES
+                ('a', '111', 'mod/ugc_111.mod', 'A', 'ready_to_play', 'steam'),
+                ('b', NULL, 'mod/ugc_222.mod', 'B', 'ready_to_play', 'steam'),
+                ('c', NULL, 'mod/local.mod', 'C', 'ready_to_play', 'local'),
+                ('d', '444', 'mod/ugc_444.mod', 'D', 'ready_to_play', 'steam'),
+                ('e', NULL, 'mod/ironworks_555.mod', 'E', 'ready_to_play', 'local');
+            INSERT INTO playsets_mods (playsetId, modId, enabled, position) VALUES
+                ('p', 'a', 1, 3), ('p', 'b', 1, 0), ('p', 'c', 1, 1), ('p', 'd', 0, 2), ('p', 'e', 1, 4);
+        ").unwrap();
+        assert_eq!(read_playset(&conn, "Main").unwrap(), ids(&["222", "111", "555"]));
+        assert!(read_playset(&conn, "Nope").is_err());
+    }
+
+    #[test]
+    fn write_playset_registers_local_mods() {
+        let mut conn = launcher_db();
+        let mods = [
+            ("Mod 111".to_owned(), "mod/ironworks_111.mod".to_owned(), "/mods/111".to_owned()),
+            ("Mod 222".to_owned(), "mod/ironworks_222.mod".to_owned(), "/mods/222".to_owned()),
+        ];
+        let now = DateTime::from_timestamp(1700000000, 0).unwrap();
+        write_playset(&mut conn, "Ironworks", &mods, now).unwrap();
+        // writing again replaces the playset's mods, reusing what was registered
+        write_playset(&mut conn, "Ironworks", &[mods[1].clone(), mods[0].clone()], now).unwrap();
+
+        let (created_on, load_order) = conn.query_row("SELECT createdOn, loadOrder FROM playsets WHERE name = 'Ironworks'", [],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))).unwrap();
+        assert_eq!(created_on, "2023-11-14 22:13:20.000 +00:00");
+        assert_eq!(load_order, "custom");
+        let registered = conn.prepare("SELECT steamId, source, dirPath, createdOn FROM mods ORDER BY gameRegistryId").unwrap()
+            .query_map([], |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get::<_, i64>(3)?)))
+            .unwrap().collect::<rusqlite::Result<Vec<_>>>().unwrap();
+        assert_eq!(registered, vec![
+            (None, "local".to_owned(), "/mods/111".to_owned(), 1700000000000),
+            (None, "local".to_owned(), "/mods/222".to_owned(), 1700000000000),
+        ]);
+        assert_eq!(read_playset(&conn, "Ironworks").unwrap(), ids(&["222", "111"]));
+    }
+
+    fn manifest_mod(id: &str, name: &str) -> Mod {
+        Mod {
+            id: id.to_owned(),
+            name: Some(name.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn irony_collection_round_trips_load_order_names_and_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("Campaign.zip");
+        let manifest = Manifest {
+            version: MANIFEST_VERSION,
+            mods: vec![manifest_mod("333", "Patch"), manifest_mod("111", "Library"), manifest_mod("222", "Overhaul")],
+        };
+        write_irony_collection(&file, &manifest).unwrap();
+
+        let read = read_irony_collection(&file).unwrap();
+        assert_eq!(read.version, MANIFEST_VERSION);
+        assert_eq!(read.mods.iter().map(|m| (m.id.as_str(), m.name.as_deref())).collect::<Vec<_>>(),
+            [("333", Some("Patch")), ("111", Some("Library")), ("222", Some("Overhaul"))]);
+    }
+
+    #[test]
+    fn read_irony_collection_falls_back_to_descriptor_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("Old.zip");
+        // older exports have no ids, and mods that aren't from the workshop have other descriptor names
+        let collection = IronyCollection {
+            name: "Old".to_owned(),
+            mods: ids(&["mod/ugc_222.mod", "mod/local.mod", "mod/ugc_abc.mod", "mod/ugc_111.mod"]),
+            mod_names: ids(&["B", "Local", "Bad", "A"]),
+            ..Default::default()
+        };
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&file).unwrap());
+        zip.start_file(IRONY_COLLECTION_FILE, zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(serde_json::to_string(&collection).unwrap().as_bytes()).unwrap();
+        zip.finish().unwrap();
+
+        let read = read_irony_collection(&file).unwrap();
+        assert_eq!(read.mods.iter().map(|m| (m.id.as_str(), m.name.as_deref())).collect::<Vec<_>>(),
+            [("222", Some("B")), ("111", Some("A"))]);
+    }
+
+    fn descriptor(name: &str, dependencies: &[&str]) -> Descriptor {
+        Descriptor {
+            name: name.to_owned(),
+            dependencies: Some(dependencies.iter().map(|d| d.to_string()).collect()),
+            remote_file_id: None,
+            supported_version: None,
+            tags: None,
+            version: None,
+        }
+    }
+
+    fn installed(dependency: bool) -> InstalledItem {
+        InstalledItem {
+            title: None,
+            time_updated: None,
+            installed_at: 0,
+            checksum: String::new(),
+            checksum_version: ChecksumVersion::LATEST,
+            dependency,
+            workshop_children: vec![],
+            rolled_back_from: None,
+        }
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn get_installed_dependencies_combines_children_and_descriptors() {
+        let descriptors = HashMap::from([
+            ("1".to_owned(), descriptor("Top", &["Library"])),
+            ("2".to_owned(), descriptor("Library", &["Not installed"])),
+            ("3".to_owned(), descriptor("Patch", &[])),
+        ]);
+        // children that aren't installed, or the item itself, are ignored
+        let children = HashMap::from([("1".to_owned(), ids(&["3", "9"])), ("3".to_owned(), ids(&["3"]))]);
+        let dependencies = get_installed_dependencies(&descriptors, &children);
+        assert_eq!(dependencies["1"], HashSet::from(["2".to_owned(), "3".to_owned()]));
+        assert!(dependencies["2"].is_empty());
+        assert!(dependencies["3"].is_empty());
+    }
+
+    #[test]
+    fn find_orphans_follows_dependencies_of_dependencies() {
+        // 1 -> 2 -> 3, and 4 -> 3
+        let dependencies = HashMap::from([
+            ("1".to_owned(), HashSet::from(["2".to_owned()])),
+            ("2".to_owned(), HashSet::from(["3".to_owned()])),
+            ("3".to_owned(), HashSet::new()),
+            ("4".to_owned(), HashSet::from(["3".to_owned()])),
+        ]);
+        let mut state = InstallState::default();
+        for id in ["2", "3"] {
+            state.items.insert(id.to_owned(), installed(true));
+        }
+        let (orphans, kept) = find_orphans(&dependencies, &HashSet::from(["1".to_owned()]), &state);
+        assert_eq!(orphans, ids(&["2"]));
+        assert!(kept.is_empty());
+        let (orphans, kept) = find_orphans(&dependencies, &HashSet::from(["1".to_owned(), "4".to_owned()]), &state);
+        assert_eq!(orphans, ids(&["2", "3"]));
+        assert!(kept.is_empty());
+    }
+
+    #[test]
+    fn find_orphans_keeps_requested_and_held_items() {
+        // 1 -> 2, 3, 4, and 3 -> 5
+        let dependencies = HashMap::from([
+            ("1".to_owned(), HashSet::from(["2".to_owned(), "3".to_owned(), "4".to_owned()])),
+            ("2".to_owned(), HashSet::new()),
+            ("3".to_owned(), HashSet::from(["5".to_owned()])),
+            ("4".to_owned(), HashSet::new()),
+            ("5".to_owned(), HashSet::new()),
+        ]);
+        let mut state = InstallState::default();
+        state.items.insert("2".to_owned(), installed(false));
+        state.items.insert("3".to_owned(), installed(true));
+        state.items.insert("5".to_owned(), installed(true));
+        state.held.insert("3".to_owned());
+        // 4 has no install record, so isn't known to be a dependency
+        let (orphans, kept) = find_orphans(&dependencies, &HashSet::from(["1".to_owned()]), &state);
+        assert!(orphans.is_empty());
+        assert_eq!(kept, ids(&["2", "3", "4"]));
+    }
+}
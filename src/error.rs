@@ -3,9 +3,15 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     Internal(String),
+    /// Something wrong with what the user asked for or their files, reported without the "internal error" prefix
+    InvalidInput(String),
     MissingWebApiKey(),
+    /// The Steam Web API answered, but not with anything usable
+    WebApi(String),
     NotInitialised(),
     WorkerExitCode(u32),
+    RequiredBy { id: String, dependents: Vec<String> },
+    #[cfg(windows)]
     Conpty(conpty::error::Error),
     Curl(curl::Error),
     FsExtra(fs_extra::error::Error),
@@ -13,6 +19,7 @@ pub enum Error {
     Jomini(jomini::Error),
     Json(serde_json::Error),
     Reqwest(reqwest::Error),
+    Sqlite(rusqlite::Error),
     TomlDe(toml::de::Error),
     TomlSer(toml::ser::Error),
     Zip(zip::result::ZipError),
@@ -20,12 +27,62 @@ pub enum Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Process exit code to report when a command aborts with this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Reqwest(_) | Error::WebApi(_) => Failure::WebApi.exit_code(),
+            _ => 1,
+        }
+    }
+}
+
+/// Problems that don't abort a command, but should still be reflected in the process exit code.
+/// Ordered by precedence, so the greatest one wins when several occur.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    ChecksumMismatch,
+    PartialDownload,
+    WebApi,
+}
+
+impl Failure {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::PartialDownload => 3,
+            Failure::ChecksumMismatch => 4,
+            Failure::WebApi => 5,
+        }
+    }
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::Internal(msg) => write!(f, "internal error: {}", msg),
+            Error::InvalidInput(msg) => write!(f, "{}", msg),
+            Error::MissingWebApiKey() => write!(f, "missing Steam Web API key"),
+            Error::WebApi(msg) => write!(f, "Steam Web API error: {}", msg),
+            Error::NotInitialised() => write!(f, "steamcmd is not installed, run init first"),
+            Error::WorkerExitCode(code) => write!(f, "worker process exited with code {}", code),
+            Error::RequiredBy { id, dependents } => write!(f, "item {} is required by {}, use --force to remove it anyway", id, dependents.join(", ")),
+            #[cfg(windows)]
+            Error::Conpty(e) => write!(f, "conpty error: {}", e),
+            Error::Curl(e) => write!(f, "curl error: {}", e),
+            Error::FsExtra(e) => write!(f, "filesystem error: {}", e),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Jomini(e) => write!(f, "descriptor parse error: {}", e),
+            Error::Json(e) => write!(f, "json error: {}", e),
+            Error::Reqwest(e) => write!(f, "http error: {}", e),
+            Error::Sqlite(e) => write!(f, "sqlite error: {}", e),
+            Error::TomlDe(e) => write!(f, "toml deserialisation error: {}", e),
+            Error::TomlSer(e) => write!(f, "toml serialisation error: {}", e),
+            Error::Zip(e) => write!(f, "zip error: {}", e),
+        }
     }
 }
 
+#[cfg(windows)]
 impl From<conpty::error::Error> for Error {
     fn from(value: conpty::error::Error) -> Self {
         Error::Conpty(value)
@@ -64,7 +121,14 @@ impl From<serde_json::Error> for Error {
 
 impl From<reqwest::Error> for Error {
     fn from(value: reqwest::Error) -> Self {
-        Error::Reqwest(value)
+        // request urls carry the Steam Web API key, which mustn't end up in logs or reports
+        Error::Reqwest(value.without_url())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(value: rusqlite::Error) -> Self {
+        Error::Sqlite(value)
     }
 }
 
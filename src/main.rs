@@ -1,33 +1,93 @@
-use std::{collections::HashSet, io::Write, iter};
+use std::{collections::{BTreeSet, HashMap, HashSet}, io::{IsTerminal, Write}, process::ExitCode};
 
-use chrono::DateTime;
-use clap::{Parser, Subcommand, Args};
-use error::{Error, Result};
-use log::{error, info};
-use schemas::{Manifest, Mod};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use clap::{Parser, Subcommand, Args, ValueEnum};
+use error::{Error, Failure, Result};
+use itertools::Itertools;
+use command::DownloadResult;
+use log::{info, warn};
+use schemas::{ActivateReport, ActivatedItem, ChecksumVersion, CommandReport, ExportReport, FileDiff, HoldReport, ItemResult, ItemStatus, ListReport, ListedItem, LockReport, Manifest, MANIFEST_VERSION, Mod, PlannedAction, PlannedItem, Report, RollbackReport, Snapshot, SnapshotAction, SnapshotReport, SnapshotSummary, SyncReport, UninstallReport, UninstalledItem, VerifiedItem, VerifyReport, VerifyStatus};
 use steam_webapi_client::SteamWebApiClient;
 
 mod command;
 mod error;
 mod schemas;
 mod steam_webapi_client;
+#[allow(dead_code, unreachable_code)]
 mod ui;
 
+/// Print human-readable output, moved to stderr when stdout is reserved for the JSON report
+macro_rules! msg {
+    ($opts:expr) => {
+        if $opts.output == OutputFormat::Json { eprintln!() } else { println!() }
+    };
+    ($opts:expr, $($arg:tt)*) => {
+        if $opts.output == OutputFormat::Json { eprintln!($($arg)*) } else { println!($($arg)*) }
+    };
+}
+
 #[tokio::main]
-async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
     if std::env::var_os("RUST_LOG").is_none() {
         std::env::set_var("RUST_LOG", "warn");
     }
     pretty_env_logger::init();
 
+    let cli = Cli::parse();
+    let output = cli.output;
+
+    let report = match run(cli).await {
+        Ok(command) => Report {
+            exit_code: failure_of(&command).map_or(0, Failure::exit_code),
+            command: Some(command),
+            error: None,
+        },
+        Err(e) => Report {
+            exit_code: e.exit_code(),
+            command: None,
+            error: Some(e.to_string()),
+        },
+    };
+
+    match output {
+        OutputFormat::Human => {
+            if let Some(e) = &report.error {
+                eprintln!("Error: {}", e);
+            }
+        },
+        OutputFormat::Json => {
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{}", json),
+                Err(e) => eprintln!("Error: failed to serialise report: {}", e),
+            }
+        },
+    }
+
+    ExitCode::from(report.exit_code)
+}
+
+/// Options shared by all commands
+struct RunOptions {
+    assume_yes: bool,
+    output: OutputFormat,
+}
+
+async fn run(cli: Cli) -> Result<CommandReport> {
     let config = command::get_config_or_default()?;
 
-    let cli = Cli::parse();
+    let assume_yes = cli.yes || !std::io::stdin().is_terminal();
+    if !cli.yes && assume_yes {
+        info!("stdin is not a terminal, assuming yes to all prompts");
+    }
+    let opts = RunOptions {
+        assume_yes,
+        output: cli.output,
+    };
 
-    match cli.command {
+    let report = match cli.command {
         CliCommand::Init => {
-            println!("Installing steamcmd");
-            let mut install = command::install_steamcmd()?;
+            msg!(opts, "Installing steamcmd");
+            let mut install = command::install_steamcmd(&config)?;
             let lines = install.take_output().into_iter();
             std::thread::spawn(move || {
                 for line in lines {
@@ -35,142 +95,414 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
                 }
             });
             install.wait()?;
-            println!("Done")
+            msg!(opts, "Done");
+            CommandReport::Init
         },
-        CliCommand::Import(file) => {
-            let contents = std::fs::read_to_string(file.file)?;
-            let manifest = serde_json::from_str::<Manifest>(&contents)?;
-            let manifest_len = manifest.mods.len();
-
-            // Calculate diff
-            let mut entries_to_download = vec![];
-            for mut entry in manifest.mods {
-                let entry_clone = entry.clone();
-                match entry.checksum.take() {
-                    None => {
-                        // no checksum to compare against, so always download
-                        entries_to_download.push((entry_clone, "No comparison checksum".to_owned()));
-                    },
-                    Some(checksum) => {
-                        info!("Looking for '{}' with workshop id '{}' checksum '{}'",
-                            entry.name.unwrap_or("<no name>".to_owned()),
-                            entry.id,
-                            checksum);
-                        let local_checksum = command::calculate_local_checksum(&entry.id)?;
-                        match local_checksum {
-                            Some(local_checksum) => {
-                                if local_checksum == checksum {
-                                    info!("Local version has matching checksum, skipping");
-                                    continue;
-                                } else {
-                                    info!("Local version has checksum mismatch, will redownload");
-                                    entries_to_download.push((entry_clone, format!("Checksum mismatch - {} local <=> import {}", local_checksum, checksum)));
-                                }
-                            },
-                            None => {
-                                info!("No local version of workshop item id '{}'", entry.id);
-                                entries_to_download.push((entry_clone, "No local version".to_owned()))
-                            },
-                        }
-                    },
-                }
-            }
-
-            // Confirm
-            println!("{} items match and {} items to be downloaded", manifest_len - entries_to_download.len(), entries_to_download.len());
-            if entries_to_download.is_empty() {
-                println!("Nothing to be done, exiting");
-                return Ok(())
-            } else {
-                println!("---------------------");
-                for entry in entries_to_download.iter() {
-                    println!("Name:          {}", entry.0.name.as_ref().unwrap_or(&"<no name>".to_owned()));
-                    println!("Workshop ID:   {}", entry.0.id);
-                    println!("Reason:        {}", entry.1);
-                    println!("---------------------");
-                }
-                print!("Confirm? [Y/n] ");
-                std::io::stdout().flush()?;
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-                if !input.trim().is_empty() && input.trim().to_lowercase() != "y" {
-                    println!("Aborting");
-                    return Ok(())
-                }
-            }
-
-            // Download
-            download(entries_to_download.into_iter().map(|t| t.0), false)?;
+        CliCommand::Import(args) => match (args.file, args.from_launcher) {
+            (_, Some(playset)) => {
+                let item_ids = command::read_launcher_playset(&playset)?;
+                msg!(opts, "Found {} Workshop items in playset '{}'", item_ids.len(), playset);
+                let client = SteamWebApiClient::new(config.steam_webapi_key);
+                CommandReport::Import(install_in_load_order(client, item_ids, args.dry_run, &opts).await?)
+            },
+            (Some(file), None) if args.format == ManifestFormat::Irony => {
+                // irony collections have no checksums, so bring their items up to date like a playset
+                let manifest = command::read_irony_collection(&file)?;
+                msg!(opts, "Found {} Workshop items in Irony collection {}", manifest.mods.len(), file);
+                let client = SteamWebApiClient::new(config.steam_webapi_key);
+                let item_ids = manifest.mods.into_iter().map(|m| m.id).collect_vec();
+                CommandReport::Import(install_in_load_order(client, item_ids, args.dry_run, &opts).await?)
+            },
+            (Some(file), None) => {
+                let manifest = command::load_manifest(file)?;
+                CommandReport::Import(sync_manifest(manifest, false, args.dry_run, &opts)?)
+            },
+            (None, None) => return Err(Error::InvalidInput("nothing to import".to_owned())),
         },
-        CliCommand::Install(item_id) => {
-            let item_id = item_id.id.to_string();
+        CliCommand::Install(args) => {
+            let item_ids = args.ids.into_iter().unique();
             let client = SteamWebApiClient::new(config.steam_webapi_key);
-            install_latest(client, iter::once(item_id)).await?;
+            // collections are installed as all of their members
+            let item_ids = command::expand_collections(&client, item_ids).await?;
+            CommandReport::Install(install_latest(client, item_ids.into_iter(), args.dry_run, &opts).await?)
         }
-        CliCommand::Export(file) => {
+        CliCommand::Export(args) if args.format == ManifestFormat::Irony => {
+            let descriptors = command::get_local_descriptors()?;
+            let install_state = command::load_install_state()?;
+            msg!(opts, "Found {} local items", descriptors.len());
+            if args.detailed {
+                warn!("Irony collections have no file digests, ignoring --detailed");
+            }
+            let mods = command::get_load_order(&descriptors, &install_state).into_iter()
+                .map(|id| Mod {
+                    name: Some(descriptors[&id].name.clone()),
+                    id,
+                    ..Default::default()
+                })
+                .collect();
+            let manifest = Manifest {
+                version: MANIFEST_VERSION,
+                mods,
+            };
+            msg!(opts, "Writing Irony collection to {}", args.file);
+            command::write_irony_collection(&args.file, &manifest)?;
+            msg!(opts, "Done");
+            CommandReport::Export(ExportReport {
+                file: args.file,
+                manifest,
+            })
+        },
+        CliCommand::Export(args) => {
             let hm = command::get_local_descriptors()?;
             let empty = hm.is_empty();
-            println!("Found {} local items", hm.len());
+            msg!(opts, "Found {} local items", hm.len());
             if !empty {
-                println!("Calculating checksums ...");
+                msg!(opts, "Calculating checksums ...");
             }
+            let install_state = command::load_install_state()?;
+            let dependencies = command::get_installed_dependencies(&hm, &command::get_recorded_workshop_children(&install_state));
+            let (ids, descs): (Vec<_>, Vec<_>) = hm.into_iter().unzip();
+            let checksums = command::calculate_local_checksums(&ids.iter()
+                .map(|id| (id.clone(), ChecksumVersion::LATEST))
+                .collect::<Vec<_>>(), true)?;
+            let files = if args.detailed {
+                command::calculate_local_file_digests_many(&ids, true)?
+            } else {
+                vec![None; ids.len()]
+            };
             let mut mods = vec![];
-            for (id, desc) in hm {
+            for (((id, desc), checksum), files) in ids.into_iter().zip(descs).zip(checksums).zip(files) {
+                let installed = install_state.items.get(&id);
                 mods.push(Mod {
-                    id: id.clone(),
                     name: Some(desc.name),
-                    checksum: Some(command::calculate_local_checksum(id)?.expect("dir should exist"))
+                    checksum: Some(checksum.expect("dir should exist")),
+                    checksum_version: ChecksumVersion::LATEST,
+                    files,
+                    time_updated: installed.and_then(|i| i.time_updated),
+                    dependency: installed.is_some_and(|i| i.dependency),
+                    dependencies: dependencies.get(&id).into_iter().flatten().cloned().sorted().collect(),
+                    version: desc.version,
+                    supported_version: desc.supported_version,
+                    id,
                 });
             }
             mods.sort_unstable_by_key(|m| m.id.to_lowercase());
 
             let manifest = Manifest {
+                version: MANIFEST_VERSION,
                 mods,
             };
             let manifest_str = serde_json::to_string_pretty(&manifest)?;
-            println!("Writing manifest to {}", file.file);
-            std::fs::write(file.file, manifest_str)?;
-            println!("Done");
+            msg!(opts, "Writing manifest to {}", args.file);
+            std::fs::write(&args.file, manifest_str)?;
+            msg!(opts, "Done");
+            CommandReport::Export(ExportReport {
+                file: args.file,
+                manifest,
+            })
         },
-        CliCommand::Update => {
+        CliCommand::Update(args) => {
             // same as install but do for all present local items
             let local_descriptors = command::get_local_descriptors()?;
             let item_ids = local_descriptors.into_keys();
 
             let client = SteamWebApiClient::new(config.steam_webapi_key);
-            install_latest(client, item_ids).await?;
+            CommandReport::Update(install_latest(client, item_ids, args.dry_run, &opts).await?)
+        },
+        CliCommand::Outdated => {
+            // plan of an update, without doing it
+            let local_descriptors = command::get_local_descriptors()?;
+            let item_ids = local_descriptors.into_keys();
+
+            let client = SteamWebApiClient::new(config.steam_webapi_key);
+            CommandReport::Outdated(install_latest(client, item_ids, true, &opts).await?)
         },
         CliCommand::Cleanup => {
-            println!("Clearing steamcmd workshop cache");
-            command::purge_download_cache()?;
-            println!("Done");
+            msg!(opts, "Clearing steamcmd workshop cache");
+            let kept = command::purge_download_cache()?;
+            if !kept.is_empty() {
+                msg!(opts, "Kept {} items the collection links to", kept.len());
+            }
+            msg!(opts, "Done");
+            CommandReport::Cleanup
+        },
+        CliCommand::Uninstall(args) => {
+            let client = SteamWebApiClient::new(config.steam_webapi_key);
+            CommandReport::Uninstall(uninstall(client, args, &opts).await?)
+        },
+        CliCommand::List(args) => CommandReport::List(list(args, &opts)?),
+        CliCommand::Verify(file) => CommandReport::Verify(verify(file, &opts)?),
+        CliCommand::Lock(args) => {
+            let client = SteamWebApiClient::new(config.steam_webapi_key);
+            CommandReport::Lock(lock(client, args, &opts).await?)
         },
+        CliCommand::Sync(args) => CommandReport::Sync(sync(args, &opts)?),
+        CliCommand::Activate(args) => CommandReport::Activate(activate(args, &opts)?),
+        CliCommand::Rollback(args) => CommandReport::Rollback(rollback(args, &opts)?),
+        CliCommand::Hold(args) => CommandReport::Hold(hold(args.ids, true, &opts)?),
+        CliCommand::Unhold(args) => CommandReport::Unhold(hold(args.ids, false, &opts)?),
+        CliCommand::Snapshot(args) => CommandReport::Snapshot(snapshot(args.command, &opts)?),
+    };
+
+    Ok(report)
+}
+
+/// Work out which non-fatal failure, if any, a command's report should be exited with
+fn failure_of(report: &CommandReport) -> Option<Failure> {
+    match report {
+        CommandReport::Import(sync) | CommandReport::Install(sync) | CommandReport::Update(sync)
+            | CommandReport::Outdated(sync) | CommandReport::Sync(sync)
+            | CommandReport::Lock(LockReport { update: Some(sync), .. }) => {
+            let webapi = (!sync.missing.is_empty()).then_some(Failure::WebApi);
+            let download = sync.results.iter()
+                .any(|r| r.status == ItemStatus::DownloadFailed)
+                .then_some(Failure::PartialDownload);
+            let checksum = sync.results.iter()
+                .any(|r| r.status == ItemStatus::ChecksumMismatch)
+                .then_some(Failure::ChecksumMismatch);
+            webapi.max(download).max(checksum)
+        },
+        CommandReport::Verify(verify) => verify.items.iter()
+            .any(|i| matches!(i.status, VerifyStatus::Mismatch | VerifyStatus::Missing | VerifyStatus::Extra))
+            .then_some(Failure::ChecksumMismatch),
+        CommandReport::Init | CommandReport::Export(_) | CommandReport::Cleanup | CommandReport::Uninstall(_)
+            | CommandReport::List(_) | CommandReport::Lock(_) | CommandReport::Activate(_)
+            | CommandReport::Rollback(_) | CommandReport::Hold(_) | CommandReport::Unhold(_) => None,
+        CommandReport::Snapshot(snapshot) => (!snapshot.damaged.is_empty()).then_some(Failure::ChecksumMismatch),
+    }
+}
+
+/// Ask the user to confirm before continuing, unless running non-interactively
+fn confirm(opts: &RunOptions) -> Result<bool> {
+    if opts.assume_yes {
+        return Ok(true);
+    }
+    if opts.output == OutputFormat::Json {
+        eprint!("Confirm? [Y/n] ");
+        std::io::stderr().flush()?;
+    } else {
+        print!("Confirm? [Y/n] ");
+        std::io::stdout().flush()?;
+    }
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(input.trim().is_empty() || input.trim().to_lowercase() == "y")
+}
+
+/// Bring the collection in line with a manifest, downloading whatever doesn't match its checksums.
+/// With `remove_extras`, installed items the manifest doesn't have are removed too, unless held
+fn sync_manifest(manifest: Manifest, remove_extras: bool, dry_run: bool, opts: &RunOptions) -> Result<SyncReport> {
+    let mut report = SyncReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    // Calculate diff, hashing everything up front so it can be done in parallel
+    let to_check = manifest.mods.iter()
+        .filter(|m| m.checksum.is_some())
+        .map(|m| (m.id.clone(), m.checksum_version))
+        .collect::<Vec<_>>();
+    // a dry run leaves the collection untouched, digest cache included
+    let mut local_checksums = command::calculate_local_checksums(&to_check, !dry_run)?.into_iter();
+    let install_state = command::load_install_state()?;
+    let mut local_descriptors = command::get_local_descriptors()?;
+    let mut entries_to_download = vec![];
+    let mut held = 0;
+    for mut entry in manifest.mods {
+        local_descriptors.remove(&entry.id);
+        let entry_clone = entry.clone();
+        let mut file_diff = None;
+        let (action, reason) = match entry.checksum.take() {
+            None => {
+                // no checksum to compare against, so always download
+                (PlannedAction::Download, "No comparison checksum".to_owned())
+            },
+            Some(checksum) => {
+                info!("Looking for '{}' with workshop id '{}' checksum '{}'",
+                    entry.name.as_deref().unwrap_or("<no name>"),
+                    entry.id,
+                    checksum);
+                // in step with to_check, which has one element for each entry with a checksum
+                let local_checksum = local_checksums.next().flatten();
+                match local_checksum {
+                    Some(local_checksum) => {
+                        if local_checksum == checksum {
+                            info!("Local version has matching checksum, skipping");
+                            (PlannedAction::Skip, "Checksum matches".to_owned())
+                        } else {
+                            info!("Local version has checksum mismatch, will redownload");
+                            file_diff = command::diff_local_files(&entry_clone, !dry_run)?;
+                            (PlannedAction::Download, format!("Checksum mismatch - {} local <=> import {}", local_checksum, checksum))
+                        }
+                    },
+                    None => {
+                        info!("No local version of workshop item id '{}'", entry.id);
+                        (PlannedAction::Download, "No local version".to_owned())
+                    },
+                }
+            },
+        };
+        // held items stay as they are, unless there's nothing installed to hold
+        let (action, reason) = if action == PlannedAction::Download
+            && install_state.held.contains(&entry_clone.id)
+            && command::is_installed(&entry_clone.id)? {
+            info!("Workshop item id '{}' is held, skipping", entry_clone.id);
+            held += 1;
+            (PlannedAction::Skip, format!("Held - {}", reason))
+        } else {
+            (action, reason)
+        };
+        report.plan.push(PlannedItem {
+            id: entry_clone.id.clone(),
+            name: entry_clone.name.clone(),
+            action,
+            reason: reason.clone(),
+            file_diff: file_diff.clone(),
+            dependency: entry_clone.dependency,
+            remote_time_updated: None,
+            local_time_updated: None,
+            expected_checksum: entry_clone.checksum.clone(),
+        });
+        if action == PlannedAction::Download {
+            entries_to_download.push((entry_clone, reason, file_diff.clone()));
+        }
     }
 
-    Ok(())
+    // whatever is left is installed but not in the manifest
+    let matched = report.plan.len() - entries_to_download.len() - held;
+    let mut entries_to_remove = vec![];
+    if remove_extras {
+        for (id, desc) in local_descriptors.into_iter().sorted_by(|a, b| a.0.cmp(&b.0)) {
+            let (action, reason) = if install_state.held.contains(&id) {
+                info!("Workshop item id '{}' is held, keeping", id);
+                held += 1;
+                (PlannedAction::Skip, "Held - not in manifest".to_owned())
+            } else {
+                (PlannedAction::Remove, "Not in manifest".to_owned())
+            };
+            report.plan.push(PlannedItem {
+                id: id.clone(),
+                name: Some(desc.name.clone()),
+                action,
+                reason,
+                file_diff: None,
+                dependency: install_state.items.get(&id).is_some_and(|i| i.dependency),
+                remote_time_updated: None,
+                local_time_updated: None,
+                expected_checksum: None,
+            });
+            if action == PlannedAction::Remove {
+                entries_to_remove.push((id, desc.name));
+            }
+        }
+    }
+
+    // Confirm
+    match (held, entries_to_remove.len()) {
+        (0, 0) => msg!(opts, "{} items match and {} items to be downloaded", matched, entries_to_download.len()),
+        (_, 0) => msg!(opts, "{} items match, {} held and {} items to be downloaded", matched, held, entries_to_download.len()),
+        (_, removals) => msg!(opts, "{} items match, {} held, {} items to be downloaded and {} items to be removed",
+            matched, held, entries_to_download.len(), removals),
+    }
+    if entries_to_download.is_empty() && entries_to_remove.is_empty() {
+        msg!(opts, "Nothing to be done, exiting");
+        return Ok(report)
+    } else {
+        msg!(opts, "---------------------");
+        for entry in entries_to_download.iter() {
+            msg!(opts, "Name:          {}", entry.0.name.as_deref().unwrap_or("<no name>"));
+            msg!(opts, "Workshop ID:   {}", entry.0.id);
+            msg!(opts, "Reason:        {}", entry.1);
+            if let Some(file_diff) = &entry.2 {
+                print_file_diff(file_diff, opts);
+            }
+            msg!(opts, "---------------------");
+        }
+        for (id, name) in entries_to_remove.iter() {
+            msg!(opts, "Name:          {}", name);
+            msg!(opts, "Workshop ID:   {}", id);
+            msg!(opts, "Reason:        Not in manifest, will be removed");
+            msg!(opts, "---------------------");
+        }
+        if dry_run {
+            msg!(opts, "Dry run, not changing anything");
+            return Ok(report)
+        }
+        if !confirm(opts)? {
+            msg!(opts, "Aborting");
+            report.aborted = true;
+            return Ok(report)
+        }
+    }
+
+    // Remove
+    for (id, name) in entries_to_remove {
+        msg!(opts, "Removing \"{}\" ({}) ...", name, id);
+        command::remove_installed_item(&id)?;
+        report.results.push(ItemResult {
+            id,
+            name: Some(name),
+            status: ItemStatus::Removed,
+            checksum: None,
+            error: None,
+            file_diff: None,
+        });
+    }
+
+    // Download
+    if !entries_to_download.is_empty() {
+        report.results.extend(download(entries_to_download.into_iter().map(|t| t.0), false, opts)?);
+    }
+    Ok(report)
+}
+
+/// Bring items from an ordered mod list up to date, remembering their order as the collection's load order
+async fn install_in_load_order(client: SteamWebApiClient, item_ids: Vec<String>, dry_run: bool, opts: &RunOptions) -> Result<SyncReport> {
+    let item_ids = item_ids.into_iter().unique().collect_vec();
+    let report = install_latest(client, item_ids.iter().cloned(), dry_run, opts).await?;
+    if !report.dry_run && !report.aborted {
+        command::save_load_order(&item_ids)?;
+    }
+    Ok(report)
 }
 
-async fn install_latest(client: SteamWebApiClient, item_ids: impl Iterator<Item = String>) -> Result<()> {
-    let workshop_details = command::fetch_workshop_details_with_dependencies(&client, item_ids).await?;
+/// Bring the requested items and all their dependencies up to date, or with `dry_run` only plan to
+async fn install_latest(client: SteamWebApiClient, item_ids: impl Iterator<Item = String>, dry_run: bool, opts: &RunOptions) -> Result<SyncReport> {
+    let requested_ids = item_ids.collect::<HashSet<_>>();
+    let workshop_details = command::fetch_workshop_details_with_dependencies(&client, requested_ids.iter().cloned()).await?;
+    let install_state = command::load_install_state()?;
+    let mut report = SyncReport {
+        dry_run,
+        ..Default::default()
+    };
 
     let mut ids_with_error = vec![];
     let mut ids_to_download = vec![];
     let mut ids_to_ignore = vec![];
+    let mut ids_rolled_back = vec![];
+    let mut ids_held = vec![];
 
     for (id, response) in workshop_details.iter() {
         match response {
             schemas::GetPublishedFileDetailsResponseItem::FileDetails(fd) => {
                 let remote_ts = DateTime::from_timestamp(fd.time_updated, 0)
                     .ok_or(Error::Internal("error constructing timestamp".to_owned()))?;
-                // desired state is all fetched entries. Compare with local descriptor if present
-                match command::get_local_created_timestamp(&id)? {
+                // desired state is all fetched entries. Compare with recorded install if present
+                match command::get_local_updated_timestamp(&install_state, id)? {
                     Some(local_ts) => {
-                        if remote_ts > local_ts {
+                        let rolled_back_from = install_state.items.get(id).and_then(|i| i.rolled_back_from);
+                        if remote_ts > local_ts && install_state.held.contains(id) {
+                            // frozen at the installed version until unheld
+                            ids_held.push((id.clone(), fd, local_ts));
+                        } else if remote_ts > local_ts && rolled_back_from.is_some_and(|ts| fd.time_updated <= ts) {
+                            // the only update on offer is the one that was rolled back from
+                            ids_rolled_back.push((id.clone(), fd, local_ts));
+                        } else if remote_ts > local_ts {
                             // remote is newer than local, should download
                             ids_to_download.push((id.clone(), fd, remote_ts, Some(local_ts)));
                         } else {
                             // remote is older than local, no need to update
-                            ids_to_ignore.push((id.clone(), fd));
+                            ids_to_ignore.push((id.clone(), fd, local_ts));
                         }
                     }
                     None => {
@@ -185,43 +517,110 @@ async fn install_latest(client: SteamWebApiClient, item_ids: impl Iterator<Item
         }
     }
 
+    ids_with_error.sort_unstable();
     if !ids_with_error.is_empty() {
-        println!("Error with checking items with ids:");
-        for id in ids_with_error {
-            println!("  {}", id);
+        msg!(opts, "Error with checking items with ids:");
+        for id in ids_with_error.iter() {
+            msg!(opts, "  {}", id);
         }
     }
+    report.missing = ids_with_error;
 
-    if ids_to_download.is_empty() {
-        println!("All items up-to-date, nothing to do");
-        return Ok(())
+    ids_to_download.sort_unstable_by_key(|(_, fd, _, _)| fd.title.to_lowercase());
+    ids_to_ignore.sort_unstable_by_key(|(_, fd, _)| fd.title.to_lowercase());
+    ids_rolled_back.sort_unstable_by_key(|(_, fd, _)| fd.title.to_lowercase());
+    ids_held.sort_unstable_by_key(|(_, fd, _)| fd.title.to_lowercase());
+
+    let held_reason = |fd: &schemas::PublishedFileDetails| format!("Held, update available since {}", format_timestamp(Some(fd.time_updated)));
+    let skipped = ids_to_ignore.iter().map(|i| (i, "Up-to-date".to_owned()))
+        .chain(ids_rolled_back.iter().map(|i| (i, "Rolled back, no newer update".to_owned())))
+        .chain(ids_held.iter().map(|i| (i, held_reason(i.1))));
+    for ((id, details, local_ts), reason) in skipped {
+        report.plan.push(PlannedItem {
+            id: id.clone(),
+            name: Some(details.title.clone()),
+            action: PlannedAction::Skip,
+            reason,
+            file_diff: None,
+            dependency: is_dependency(&install_state, &requested_ids, id),
+            remote_time_updated: Some(details.time_updated),
+            local_time_updated: Some(local_ts.timestamp()),
+            expected_checksum: None,
+        });
+    }
+    for (id, details, _, local_ts) in ids_to_download.iter() {
+        let dependency = is_dependency(&install_state, &requested_ids, id);
+        let reason = match (local_ts, dependency) {
+            (Some(_), _) => "Update available",
+            (None, true) => "New dependency",
+            (None, false) => "No local version",
+        };
+        report.plan.push(PlannedItem {
+            id: id.clone(),
+            name: Some(details.title.clone()),
+            action: PlannedAction::Download,
+            reason: reason.to_owned(),
+            file_diff: None,
+            dependency,
+            remote_time_updated: Some(details.time_updated),
+            local_time_updated: local_ts.map(|ts| ts.timestamp()),
+            expected_checksum: None,
+        });
     }
 
-    ids_to_download.sort_unstable_by_key(|(_, fd, _, _)| fd.title.to_lowercase());
-    ids_to_ignore.sort_unstable_by_key(|(_, fd)| fd.title.to_lowercase());
+    if !ids_held.is_empty() {
+        msg!(opts, "Items held:");
+        for (_, details, _) in ids_held.iter() {
+            msg!(opts, "  {} ({})", &details.title, held_reason(details).to_lowercase());
+        }
+        msg!(opts);
+    }
+
+    if !ids_rolled_back.is_empty() {
+        msg!(opts, "Items rolled back, skipped until a newer update:");
+        for (_, details, _) in ids_rolled_back.iter() {
+            msg!(opts, "  {}", &details.title);
+        }
+        msg!(opts);
+    }
 
-    println!("Items up-to-date:");
-    for (_, details) in ids_to_ignore.iter() {
-        println!("  {}", &details.title);
+    if ids_to_download.is_empty() {
+        if ids_held.is_empty() && ids_rolled_back.is_empty() {
+            msg!(opts, "All items up-to-date, nothing to do");
+        } else {
+            msg!(opts, "Nothing else to update, nothing to do");
+        }
+        return Ok(report)
     }
-    println!();
 
-    println!("Items to be downloaded:");
-    println!("{:-^48}|{:-^21}|{:-^21}", "Name", "Latest", "Current");
-    for (_, details, remote_ts, local_ts) in ids_to_download.iter() {
+    msg!(opts, "Items up-to-date:");
+    for (_, details, _) in ids_to_ignore.iter() {
+        msg!(opts, "  {}", &details.title);
+    }
+    msg!(opts);
+
+    msg!(opts, "Items to be downloaded:");
+    msg!(opts, "{:-^48}|{:-^21}|{:-^21}", "Name", "Latest", "Current");
+    for (id, details, remote_ts, local_ts) in ids_to_download.iter() {
         let remote_ts = remote_ts.format("%F %X");
-        let local_ts = local_ts.map_or("<none>".to_owned(), |ts| ts.format("%F %X").to_string());
-        println!("  {:<45}   {}   {}", &details.title, remote_ts, local_ts);
+        let local_ts = match local_ts {
+            Some(ts) => ts.format("%F %X").to_string(),
+            None if !requested_ids.contains(id) => "<new dependency>".to_owned(),
+            None => "<none>".to_owned(),
+        };
+        msg!(opts, "  {:<45}   {}   {}", &details.title, remote_ts, local_ts);
     }
-    println!();
+    msg!(opts);
 
-    print!("Confirm? [Y/n] ");
-    std::io::stdout().flush()?;
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input)?;
-    if !input.trim().is_empty() && input.trim().to_lowercase() != "y" {
-        println!("Aborting");
-        return Ok(())
+    if dry_run {
+        msg!(opts, "Dry run, not downloading anything");
+        return Ok(report)
+    }
+
+    if !confirm(opts)? {
+        msg!(opts, "Aborting");
+        report.aborted = true;
+        return Ok(report)
     }
 
     // massage into old mods format
@@ -229,67 +628,876 @@ async fn install_latest(client: SteamWebApiClient, item_ids: impl Iterator<Item
         id: id.clone(),
         name: Some(details.title.clone()),
         checksum: None,
+        checksum_version: ChecksumVersion::LATEST,
+        files: None,
+        time_updated: Some(details.time_updated),
+        dependency: is_dependency(&install_state, &requested_ids, &id),
+        dependencies: details.children.iter().flatten().map(|c| c.publishedfileid.clone()).collect(),
+        version: None,
+        supported_version: None,
     });
 
-    download(entries_to_download, true)
+    report.results = download(entries_to_download, true, opts)?;
+    Ok(report)
 }
 
-fn download(entries_to_download: impl Iterator<Item = Mod>, ignore_checksum: bool) -> Result<()> {
-    let mut errors = 0;
-    for entry in entries_to_download {
-        println!("Downloading \"{}\" ({}) ...", entry.name.unwrap_or("<no name>".to_owned()), entry.id);
-        let mut download = command::download_workshop_item(&entry.id)?;
-        let lines = download.take_output().into_iter();
-        std::thread::spawn(move || {
-            for line in lines {
-                info!("{}", line);
+/// Whether an item is a dependency rather than requested itself. Installed items keep what was recorded when they
+/// were first installed, as `update` requests every installed item
+fn is_dependency(install_state: &schemas::InstallState, requested_ids: &HashSet<String>, id: &str) -> bool {
+    match install_state.items.get(id) {
+        Some(item) => item.dependency,
+        None => !requested_ids.contains(id),
+    }
+}
+
+/// Write the lock file for a desired set. Unpinned items and their dependencies are locked as installed,
+/// after being brought up to date with `--update`. Pinned items keep their previously locked entry
+async fn lock(client: SteamWebApiClient, args: LockArgs, opts: &RunOptions) -> Result<LockReport> {
+    let desired = command::load_desired_set(&args.file)?;
+    let lock_file = command::get_lock_file(&args.file);
+    let mut previous = if lock_file.is_file() {
+        command::load_manifest(&lock_file)?.mods.into_iter().map(|m| (m.id.clone(), m)).collect()
+    } else {
+        HashMap::new()
+    };
+    let mut report = LockReport {
+        file: lock_file.display().to_string(),
+        update: None,
+        manifest: None,
+    };
+
+    let mut requested = BTreeSet::new();
+    let mut pinned = HashMap::new();
+    for item in desired.mods {
+        let id = parse_workshop_id(&item.id).map_err(Error::InvalidInput)?;
+        match previous.remove(&id) {
+            Some(entry) if item.pin => {
+                msg!(opts, "Keeping pinned {} ({}){}",
+                    entry.name.as_deref().unwrap_or("<no name>"),
+                    id,
+                    item.note.map(|n| format!(": {}", n)).unwrap_or_default());
+                pinned.insert(id, entry);
+            },
+            _ => {
+                if item.pin {
+                    warn!("{} is pinned but not locked yet, locking it as installed", id);
+                }
+                requested.insert(id);
+            },
+        }
+    }
+
+    if args.update {
+        let sync = install_latest(client, requested.iter().cloned(), args.dry_run, opts).await?;
+        let done = !sync.aborted && !sync.dry_run;
+        report.update = Some(sync);
+        if !done {
+            return Ok(report);
+        }
+    }
+
+    // everything reachable from the requested items, except what is pinned
+    let descriptors = command::get_local_descriptors()?;
+    let install_state = command::load_install_state()?;
+    let recorded_children = command::get_recorded_workshop_children(&install_state);
+    let installed_dependencies = command::get_installed_dependencies(&descriptors, &recorded_children);
+    let dependencies_of = |id: &str| recorded_children.get(id).into_iter()
+        .flatten()
+        .chain(installed_dependencies.get(id).into_iter().flatten())
+        .filter(|dep| *dep != id)
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    let mut resolved = BTreeSet::new();
+    let mut queue = requested.iter().cloned()
+        .chain(pinned.values().flat_map(|m| m.dependencies.iter().cloned()))
+        .collect_vec();
+    while let Some(id) = queue.pop() {
+        if pinned.contains_key(&id) || !resolved.insert(id.clone()) {
+            continue;
+        }
+        queue.extend(dependencies_of(&id));
+    }
+
+    msg!(opts, "Calculating checksums ...");
+    let resolved = resolved.into_iter().collect_vec();
+    let checksums = command::calculate_local_checksums(&resolved.iter()
+        .map(|id| (id.clone(), ChecksumVersion::LATEST))
+        .collect_vec(), true)?;
+    let mut mods = pinned.into_values()
+        .map(|entry| Mod { dependency: false, ..entry })
+        .collect_vec();
+    for (id, checksum) in resolved.into_iter().zip(checksums) {
+        if checksum.is_none() {
+            warn!("{} is not installed, locking it without a checksum. Run lock --update to install it", id);
+        }
+        let descriptor = descriptors.get(&id);
+        let installed = install_state.items.get(&id);
+        mods.push(Mod {
+            name: descriptor.map(|d| d.name.clone()).or_else(|| installed.and_then(|i| i.title.clone())),
+            checksum,
+            checksum_version: ChecksumVersion::LATEST,
+            files: None,
+            time_updated: installed.and_then(|i| i.time_updated),
+            dependency: !requested.contains(&id),
+            dependencies: dependencies_of(&id).into_iter().collect(),
+            version: descriptor.and_then(|d| d.version.clone()),
+            supported_version: descriptor.and_then(|d| d.supported_version.clone()),
+            id,
+        });
+    }
+    mods.sort_unstable_by_key(|m| m.id.to_lowercase());
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        mods,
+    };
+    msg!(opts, "Writing {} locked items to {}", manifest.mods.len(), report.file);
+    std::fs::write(&lock_file, serde_json::to_string_pretty(&manifest)?)?;
+    msg!(opts, "Done");
+    report.manifest = Some(manifest);
+    Ok(report)
+}
+
+/// Install exactly the state recorded in the lock file of a desired set, removing installed items it doesn't record
+fn sync(args: SyncArgs, opts: &RunOptions) -> Result<SyncReport> {
+    let lock_file = command::get_lock_file(&args.file);
+    if !lock_file.is_file() {
+        return Err(Error::InvalidInput(format!("{} not found, create it with the lock command", lock_file.display())));
+    }
+    let manifest = command::load_manifest(&lock_file)?;
+
+    // a lock that doesn't cover everything desired is stale
+    if std::path::Path::new(&args.file).is_file() {
+        let locked = manifest.mods.iter().map(|m| m.id.as_str()).collect::<HashSet<_>>();
+        let unlocked = command::load_desired_set(&args.file)?.mods.into_iter()
+            .map(|item| parse_workshop_id(&item.id).map_err(Error::InvalidInput))
+            .filter_ok(|id| !locked.contains(id.as_str()))
+            .collect::<Result<Vec<_>>>()?;
+        if !unlocked.is_empty() {
+            return Err(Error::InvalidInput(format!("{} is out of date, missing {}. Run the lock command first",
+                lock_file.display(),
+                unlocked.join(", "))));
+        }
+    }
+
+    sync_manifest(manifest, true, args.dry_run, opts)
+}
+
+/// Write descriptors for every installed item into the game's `mod/` folder and enable them in load order
+fn activate(args: ActivateArgs, opts: &RunOptions) -> Result<ActivateReport> {
+    let descriptors = command::get_local_descriptors()?;
+    let install_state = command::load_install_state()?;
+    let ordered = command::get_load_order(&descriptors, &install_state);
+    let mut report = ActivateReport::default();
+
+    msg!(opts, "Writing descriptors for {} items ...", ordered.len());
+    for id in ordered {
+        let descriptor = command::write_mod_descriptor(&id)?;
+        report.enabled.push(ActivatedItem {
+            name: descriptors[&id].name.clone(),
+            id,
+            descriptor,
+        });
+    }
+    report.removed = command::remove_stale_mod_descriptors(&descriptors.keys().cloned().collect())?;
+    if !report.removed.is_empty() {
+        msg!(opts, "Removed descriptors of {} items no longer installed", report.removed.len());
+    }
+
+    // the playset is written in a single transaction, so do it first and leave dlc_load.json alone if it fails
+    if let Some(playset) = args.playset {
+        let mods = report.enabled.iter()
+            .map(|i| (i.id.clone(), i.name.clone(), i.descriptor.clone()))
+            .collect_vec();
+        command::write_launcher_playset(&playset, &mods)?;
+        msg!(opts, "Wrote Paradox Launcher playset '{}'", playset);
+        report.playset = Some(playset);
+    }
+
+    let enabled_mods = report.enabled.iter().map(|i| i.descriptor.clone()).collect_vec();
+    let dlc_load = command::write_dlc_load(&enabled_mods)?;
+    report.dlc_load = dlc_load.display().to_string();
+    msg!(opts, "Enabled {} mods in {}", enabled_mods.len(), report.dlc_load);
+    msg!(opts, "Done");
+    Ok(report)
+}
+
+/// Swap an item back to a version kept from before it was replaced, or list the kept versions
+fn rollback(args: RollbackArgs, opts: &RunOptions) -> Result<RollbackReport> {
+    let install_state = command::load_install_state()?;
+    let mut report = RollbackReport {
+        available: install_state.retained.get(&args.id).cloned().unwrap_or_default(),
+        id: args.id,
+        restored: None,
+    };
+    report.available.sort_by_key(|v| v.installed_at);
+
+    if args.list {
+        if report.available.is_empty() {
+            msg!(opts, "No kept versions of {}", report.id);
+        } else {
+            msg!(opts, "{:-^21}|{:-^21}|{:-^12}", "Installed", "Workshop updated", "Size");
+            for version in report.available.iter() {
+                msg!(opts, "  {:<19}   {:<19}   {:>9}", format_timestamp(Some(version.installed_at)), format_timestamp(version.time_updated), format_size(version.size));
             }
+            msg!(opts, "Use --to with an installed time to pick one");
+        }
+        return Ok(report);
+    }
+
+    let restored = command::rollback_item(&report.id, args.to)?;
+    msg!(opts, "Rolled back {} to the version installed {}",
+        restored.title.as_deref().unwrap_or(&report.id),
+        format_timestamp(Some(restored.installed_at)));
+    report.restored = Some(restored);
+    Ok(report)
+}
+
+fn format_timestamp(ts: Option<i64>) -> String {
+    ts.and_then(|ts| DateTime::from_timestamp(ts, 0))
+        .map_or("<unknown>".to_owned(), |ts| ts.format("%F %X").to_string())
+}
+
+/// Hold or unhold items. Only installed items can be held
+fn hold(ids: Vec<String>, held: bool, opts: &RunOptions) -> Result<HoldReport> {
+    let descriptors = command::get_local_descriptors()?;
+    let listing = held && ids.is_empty();
+    let mut ids = ids.into_iter().unique().collect_vec();
+    if held {
+        ids.retain(|id| {
+            let installed = descriptors.contains_key(id);
+            if !installed {
+                msg!(opts, "Item {} is not installed, skipping", id);
+            }
+            installed
         });
-        if let Err(e) = download.wait() {
-            error!("Download failed with error: {:?}", e);
+    }
+
+    let (changed, all_held) = command::set_held(&ids, held)?;
+    let name = |id: &String| descriptors.get(id).map_or(id.clone(), |d| format!("{} ({})", d.name, id));
+    for id in ids.iter().filter(|id| !changed.contains(id)) {
+        msg!(opts, "{} is already {}", name(id), if held { "held" } else { "not held" });
+    }
+    for id in changed.iter() {
+        msg!(opts, "{} {}", if held { "Held" } else { "Unheld" }, name(id));
+    }
+    if listing {
+        if all_held.is_empty() {
+            msg!(opts, "No items are held");
+        } else {
+            msg!(opts, "Held items:");
+            for id in all_held.iter() {
+                msg!(opts, "  {}", name(id));
+            }
+        }
+    }
+    Ok(HoldReport {
+        changed,
+        held: all_held,
+    })
+}
+
+/// Create, list, restore or delete snapshots of the whole collection
+fn snapshot(command: SnapshotCommand, opts: &RunOptions) -> Result<SnapshotReport> {
+    let summary = |snapshot: &Snapshot| SnapshotSummary {
+        name: snapshot.name.clone(),
+        created_at: snapshot.created_at,
+        items: snapshot.items.len(),
+        size: snapshot.items.values().map(|i| i.size).sum(),
+    };
+    let mut report = SnapshotReport {
+        action: SnapshotAction::List,
+        snapshots: vec![],
+        restored: vec![],
+        removed: vec![],
+        damaged: vec![],
+        copied_size: None,
+        aborted: false,
+        dry_run: false,
+    };
+
+    match command {
+        SnapshotCommand::Create(args) => {
+            let name = args.name.unwrap_or_else(|| chrono::Utc::now().format("%Y%m%d-%H%M%S").to_string());
+            msg!(opts, "Taking snapshot {} ...", name);
+            let (snapshot, copied) = command::create_snapshot(&name)?;
+            let summary = summary(&snapshot);
+            msg!(opts, "Snapshot {} taken of {} items, {} of {} had to be copied",
+                name, summary.items, format_size(copied), format_size(summary.size));
+            report.action = SnapshotAction::Create;
+            report.snapshots.push(summary);
+            report.copied_size = Some(copied);
+        },
+        SnapshotCommand::List => {
+            report.snapshots = command::list_snapshots()?.iter().map(summary).collect();
+            if report.snapshots.is_empty() {
+                msg!(opts, "No snapshots");
+            } else {
+                msg!(opts, "{:-^32}|{:-^21}|{:-^8}|{:-^12}", "Name", "Created", "Items", "Size");
+                for s in report.snapshots.iter() {
+                    msg!(opts, "  {:<30}   {:<19}   {:>5}   {:>9}", s.name, format_timestamp(Some(s.created_at)), s.items, format_size(s.size));
+                }
+            }
+        },
+        SnapshotCommand::Restore(args) => {
+            report.action = SnapshotAction::Restore;
+            report.dry_run = args.dry_run;
+            let snapshot = command::load_snapshot(&args.name)?;
+            msg!(opts, "Checking snapshot {} against the collection ...", snapshot.name);
+            let plan = command::plan_snapshot_restore(&snapshot)?;
+            report.snapshots.push(summary(&snapshot));
+
+            let descriptors = command::get_local_descriptors()?;
+            let name = |id: &String| descriptors.get(id).map(|d| d.name.clone())
+                .or_else(|| snapshot.items[id].installed.as_ref().and_then(|i| i.title.clone()))
+                .map_or(id.clone(), |name| format!("{} ({})", name, id));
+            for id in plan.damaged.iter() {
+                msg!(opts, "Copy of {} in snapshot {} has been modified since it was taken, leaving it as installed",
+                    name(id), snapshot.name);
+            }
+            report.damaged = plan.damaged.clone();
+            if plan.is_empty() {
+                msg!(opts, "Collection already matches snapshot {}", snapshot.name);
+                return Ok(report);
+            }
+            msg!(opts, "{} items to restore and {} items to remove", plan.changed.len(), plan.removed.len());
+            for id in plan.changed.iter() {
+                msg!(opts, "  Restore {}", name(id));
+            }
+            for id in plan.removed.iter() {
+                msg!(opts, "  Remove {}", name(id));
+            }
+            if plan.records_changed {
+                msg!(opts, "  Restore install records and load order");
+            }
+            report.restored = plan.changed.clone();
+            report.removed = plan.removed.clone();
+            if args.dry_run {
+                return Ok(report);
+            }
+            if !confirm(opts)? {
+                msg!(opts, "Aborted");
+                report.aborted = true;
+                return Ok(report);
+            }
+            command::restore_snapshot(&snapshot, &plan)?;
+            msg!(opts, "Restored snapshot {}", snapshot.name);
+        },
+        SnapshotCommand::Delete(args) => {
+            let snapshot = command::delete_snapshot(&args.name)?;
+            msg!(opts, "Deleted snapshot {}", snapshot.name);
+            report.action = SnapshotAction::Delete;
+            report.snapshots.push(summary(&snapshot));
+        },
+    }
+    Ok(report)
+}
+
+/// Compare the installed collection against a manifest without changing anything
+fn verify(file: FileArg, opts: &RunOptions) -> Result<VerifyReport> {
+    let manifest = command::load_manifest(file.file)?;
+    let mut local_descriptors = command::get_local_descriptors()?;
+    let mut report = VerifyReport::default();
+
+    if !manifest.mods.is_empty() {
+        msg!(opts, "Calculating checksums ...");
+    }
+    // verify only reads the collection, so leaves the digest cache as it was
+    let local_checksums = command::calculate_local_checksums(&manifest.mods.iter()
+        .map(|m| (m.id.clone(), m.checksum_version))
+        .collect::<Vec<_>>(), false)?;
+    for (entry, local_checksum) in manifest.mods.into_iter().zip(local_checksums) {
+        local_descriptors.remove(&entry.id);
+        let status = match (&entry.checksum, &local_checksum) {
+            (_, None) => VerifyStatus::Missing,
+            (None, Some(_)) => VerifyStatus::Unverified,
+            (Some(expected), Some(local)) if expected == local => VerifyStatus::Match,
+            (Some(_), Some(_)) => VerifyStatus::Mismatch,
+        };
+        let file_diff = if status == VerifyStatus::Mismatch {
+            command::diff_local_files(&entry, false)?
+        } else {
+            None
+        };
+        report.items.push(VerifiedItem {
+            id: entry.id,
+            name: entry.name,
+            status,
+            expected_checksum: entry.checksum,
+            local_checksum,
+            file_diff,
+        });
+    }
+
+    // whatever is left is installed but wasn't asked for
+    let extras = local_descriptors.into_iter().sorted_by(|a, b| a.0.cmp(&b.0)).collect::<Vec<_>>();
+    let extra_checksums = command::calculate_local_checksums(&extras.iter()
+        .map(|(id, _)| (id.clone(), ChecksumVersion::LATEST))
+        .collect::<Vec<_>>(), false)?;
+    for ((id, desc), local_checksum) in extras.into_iter().zip(extra_checksums) {
+        report.items.push(VerifiedItem {
+            local_checksum,
+            id,
+            name: Some(desc.name),
+            status: VerifyStatus::Extra,
+            expected_checksum: None,
+            file_diff: None,
+        });
+    }
+
+    let count = |status| report.items.iter().filter(|i| i.status == status).count();
+    for item in report.items.iter().filter(|i| i.status != VerifyStatus::Match) {
+        let status = match item.status {
+            VerifyStatus::Match => "OK",
+            VerifyStatus::Mismatch => "MISMATCH",
+            VerifyStatus::Missing => "MISSING",
+            VerifyStatus::Unverified => "UNVERIFIED",
+            VerifyStatus::Extra => "EXTRA",
+        };
+        msg!(opts, "  {:<12} {:<12} {}", status, item.id, item.name.as_deref().unwrap_or("<no name>"));
+        if let Some(file_diff) = &item.file_diff {
+            print_file_diff(file_diff, opts);
+        }
+    }
+    msg!(opts, "{} match, {} mismatch, {} missing, {} unverified, {} extra",
+        count(VerifyStatus::Match),
+        count(VerifyStatus::Mismatch),
+        count(VerifyStatus::Missing),
+        count(VerifyStatus::Unverified),
+        count(VerifyStatus::Extra));
+
+    Ok(report)
+}
+
+/// Print the files that differ between a local copy and a manifest, one per line
+fn print_file_diff(diff: &FileDiff, opts: &RunOptions) {
+    for path in diff.added.iter() {
+        msg!(opts, "      + {}", path);
+    }
+    for path in diff.removed.iter() {
+        msg!(opts, "      - {}", path);
+    }
+    for path in diff.changed.iter() {
+        msg!(opts, "      ~ {}", path);
+    }
+}
+
+fn list(args: ListArgs, opts: &RunOptions) -> Result<ListReport> {
+    let install_state = command::load_install_state()?;
+    let mut report = ListReport::default();
+
+    for (id, desc) in command::get_local_descriptors()? {
+        let tags = desc.tags.unwrap_or_default();
+        if let Some(tag) = &args.tag {
+            if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+                continue;
+            }
+        }
+        if let Some(pattern) = &args.supported_version {
+            // versions are written both with and without a leading v
+            let matches = desc.supported_version.as_deref()
+                .is_some_and(|v| glob_match(pattern.trim_start_matches('v'), v.trim_start_matches('v')));
+            if !matches {
+                continue;
+            }
+        }
+        if let Some(name) = &args.name {
+            if !desc.name.to_lowercase().contains(&name.to_lowercase()) {
+                continue;
+            }
+        }
+        report.items.push(ListedItem {
+            size: command::get_local_size(&id)?.unwrap_or_default(),
+            installed_at: command::get_local_installed_timestamp(&install_state, &id)?.map(|ts| ts.timestamp()),
+            id,
+            name: desc.name,
+            version: desc.version,
+            supported_version: desc.supported_version,
+            tags,
+        });
+    }
+
+    match args.sort {
+        ListSort::Name => report.items.sort_by_key(|i| i.name.to_lowercase()),
+        ListSort::Id => report.items.sort_by_key(|i| i.id.parse::<u64>().unwrap_or(u64::MAX)),
+        ListSort::Size => report.items.sort_by_key(|i| i.size),
+        ListSort::Installed => report.items.sort_by_key(|i| i.installed_at),
+    }
+    if args.reverse {
+        report.items.reverse();
+    }
+
+    msg!(opts, "{:<12} {:<40} {:<10} {:<10} {:>10} {:<10}  Tags", "ID", "Name", "Version", "Supports", "Size", "Installed");
+    for item in report.items.iter() {
+        let installed = item.installed_at
+            .and_then(|ts| DateTime::from_timestamp(ts, 0))
+            .map_or("<unknown>".to_owned(), |ts| ts.format("%F").to_string());
+        msg!(opts, "{:<12} {:<40} {:<10} {:<10} {:>10} {:<10}  {}",
+            item.id,
+            item.name,
+            item.version.as_deref().unwrap_or("-"),
+            item.supported_version.as_deref().unwrap_or("-"),
+            format_size(item.size),
+            installed,
+            item.tags.join(", "));
+    }
+    msg!(opts, "{} items", report.items.len());
+
+    Ok(report)
+}
+
+/// Match `text` against a glob `pattern` where `*` matches any run of characters and `?` any single character
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // position of the last * seen, and where in the text it started matching
+    let mut backtrack = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = backtrack {
+            p = star_p + 1;
+            t = star_t + 1;
+            backtrack = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+async fn uninstall(client: SteamWebApiClient, args: UninstallArgs, opts: &RunOptions) -> Result<UninstallReport> {
+    let descriptors = command::get_local_descriptors()?;
+    let mut report = UninstallReport::default();
+
+    let mut requested = vec![];
+    for id in args.ids.into_iter().unique() {
+        if descriptors.contains_key(&id) {
+            requested.push(id);
+        } else {
+            msg!(opts, "Item {} is not installed, skipping", id);
+        }
+    }
+    if requested.is_empty() {
+        msg!(opts, "Nothing to be done, exiting");
+        return Ok(report)
+    }
+
+    // workshop children are needed to know what depends on what, fall back to what was recorded at install time
+    let workshop_children = match command::fetch_workshop_details_with_dependencies(&client, descriptors.keys().cloned()).await {
+        Ok(details) => command::get_workshop_children(&details),
+        Err(e) => {
+            warn!("Could not fetch Workshop details, using dependencies recorded at install time: {}", e);
+            command::get_recorded_workshop_children(&command::load_install_state()?)
+        },
+    };
+    let dependencies = command::get_installed_dependencies(&descriptors, &workshop_children);
+    let dependents_of = |id: &str, removing: &HashSet<String>| {
+        dependencies.iter()
+            .filter(|(other, deps)| !removing.contains(*other) && deps.contains(id))
+            .map(|(other, _)| other.clone())
+            .sorted()
+            .collect::<Vec<_>>()
+    };
+    let name_of = |id: &str| descriptors.get(id).map(|d| d.name.clone());
+
+    let mut removing = requested.iter().cloned().collect::<HashSet<_>>();
+    let mut orphans = vec![];
+    if args.remove_orphans {
+        let (found, kept) = command::find_orphans(&dependencies, &removing, &command::load_install_state()?);
+        removing.extend(found.iter().cloned());
+        orphans = found;
+        report.kept = kept;
+    }
+
+    for id in requested.iter() {
+        let required_by = dependents_of(id, &removing);
+        if !required_by.is_empty() {
+            let dependent_names = required_by.iter()
+                .map(|d| format!("\"{}\" ({})", name_of(d).unwrap_or_default(), d))
+                .collect::<Vec<_>>();
+            if !args.force {
+                return Err(Error::RequiredBy { id: id.clone(), dependents: dependent_names })
+            }
+            msg!(opts, "Warning: \"{}\" ({}) is still required by {}", name_of(id).unwrap_or_default(), id, dependent_names.join(", "));
+        }
+        report.removed.push(UninstalledItem {
+            id: id.clone(),
+            name: name_of(id),
+            orphan: false,
+            required_by,
+        });
+    }
+    for id in orphans {
+        report.removed.push(UninstalledItem {
+            name: name_of(&id),
+            id,
+            orphan: true,
+            required_by: vec![],
+        });
+    }
+
+    msg!(opts, "Items to be removed:");
+    for item in report.removed.iter() {
+        let suffix = if item.orphan { " (no longer needed)" } else { "" };
+        msg!(opts, "  {} ({}){}", item.name.as_deref().unwrap_or("<no name>"), item.id, suffix);
+    }
+    msg!(opts);
+    if !report.kept.is_empty() {
+        msg!(opts, "No longer needed, but kept as requested or held:");
+        for id in report.kept.iter() {
+            msg!(opts, "  {} ({})", name_of(id).as_deref().unwrap_or("<no name>"), id);
+        }
+        msg!(opts);
+    }
+
+    if !confirm(opts)? {
+        msg!(opts, "Aborting");
+        report.aborted = true;
+        return Ok(report)
+    }
+
+    for item in report.removed.iter() {
+        msg!(opts, "Removing \"{}\" ({}) ...", item.name.as_deref().unwrap_or("<no name>"), item.id);
+        command::remove_installed_item(&item.id)?;
+    }
+    msg!(opts, "Done");
+
+    Ok(report)
+}
+
+/// Download and install entries, recording each in the install state along with its Workshop `time_updated` if known.
+/// Items that fail to download, copy or hash are reported as failed without holding back the rest
+fn download(entries_to_download: impl Iterator<Item = Mod>, ignore_checksum: bool, opts: &RunOptions) -> Result<Vec<ItemResult>> {
+    let entries = entries_to_download.collect::<Vec<_>>();
+    let mut item_results = vec![];
+    let mut errors = 0;
+
+    // download everything in a single steamcmd session, as concurrent sessions would share its install directory
+    let mut results = HashMap::new();
+    if !entries.is_empty() {
+        msg!(opts, "Downloading {} items ...", entries.len());
+        let ids = entries.iter().map(|entry| entry.id.clone()).collect::<Vec<_>>();
+        // a session that can't be started at all is an error, not a partial download
+        results = run_download_session(&ids)?;
+    }
+
+    let mut copied = vec![];
+    for entry in entries {
+        let name = entry.name.as_deref().unwrap_or("<no name>");
+        let download_error = match results.remove(&entry.id) {
+            Some(DownloadResult::Success) => None,
+            Some(DownloadResult::Failed(reason)) => Some(reason),
+            None => Some("no result reported by steamcmd".to_owned()),
+        };
+        if let Some(reason) = download_error {
+            msg!(opts, "Download of \"{}\" ({}) failed: {}", name, entry.id, reason);
+            errors += 1;
+            item_results.push(failed_item_result(entry, reason));
+            continue;
+        }
+        msg!(opts, "Downloaded \"{}\" ({}), copying to output ...", name, entry.id);
+        if let Err(e) = command::copy_downloaded_workshop_item(&entry.id) {
+            let reason = format!("failed to copy to output: {}", e);
+            msg!(opts, "Install of \"{}\" ({}) failed: {}", name, entry.id, reason);
             errors += 1;
+            item_results.push(failed_item_result(entry, reason));
             continue;
         }
-        println!("Download complete, copying to output ...");
-        command::copy_downloaded_workshop_item(&entry.id)?;
+        copied.push(entry);
+    }
+    if copied.is_empty() {
+        if errors != 0 {
+            msg!(opts, "Done with {} errors", errors);
+        } else {
+            msg!(opts, "Done");
+        }
+        return Ok(item_results);
+    }
+
+    // hash everything in one pass over the digest cache. Older manifests need comparing with the algorithm
+    // they were made with, as well as the latest one that is recorded
+    msg!(opts, "Copied {} items to output, computing checksums ...", copied.len());
+    let legacy = |entry: &Mod| entry.checksum.is_some() && entry.checksum_version != ChecksumVersion::LATEST;
+    let items = copied.iter().map(|entry| (entry.id.clone(), ChecksumVersion::LATEST))
+        .chain(copied.iter().filter(|entry| legacy(entry)).map(|entry| (entry.id.clone(), entry.checksum_version)))
+        .collect::<Vec<_>>();
+    let mut checksums = command::calculate_local_checksums_each(&items, true)?.into_iter()
+        .zip(items)
+        .map(|(checksum, (id, _))| checksum.and_then(|c| c.ok_or(Error::Internal(format!("item {} is not installed", id)))));
+    let latest = checksums.by_ref().take(copied.len()).collect::<Vec<_>>();
+    let mut entries = vec![];
+    for (mut entry, latest) in copied.into_iter().zip(latest) {
+        let legacy_checksum = legacy(&entry).then(|| checksums.next().expect("one checksum per legacy entry"));
+        let checksums = latest.and_then(|latest| match legacy_checksum {
+            Some(checksum) => Ok((latest, checksum?)),
+            None => Ok((latest.clone(), latest)),
+        });
+        let (latest, checksum) = match checksums {
+            Ok(checksums) => checksums,
+            Err(e) => {
+                // the copy is in place but can't be vouched for, so leave it out of the install state
+                let reason = format!("failed to compute checksum: {}", e);
+                msg!(opts, "Install of \"{}\" ({}) failed: {}", entry.name.as_deref().unwrap_or("<no name>"), entry.id, reason);
+                errors += 1;
+                item_results.push(failed_item_result(entry, reason));
+                continue;
+            }
+        };
+        // steamcmd always fetches the latest revision, so a manifest's time_updated only holds if the contents match it
+        if !ignore_checksum && entry.checksum.as_ref() != Some(&checksum) {
+            entry.time_updated = None;
+        }
+        entries.push((entry, latest, checksum));
+    }
+    command::record_installed_items(entries.iter().map(|(entry, latest, _)| (entry, latest.as_str())))?;
+
+    for (entry, latest, checksum) in entries {
+        let mut item_result = ItemResult {
+            id: entry.id.clone(),
+            name: entry.name.clone(),
+            status: ItemStatus::Installed,
+            checksum: Some(latest),
+            error: None,
+            file_diff: None,
+        };
         if !ignore_checksum {
-            println!("Copied to output, computing checksum ...");
-            let checksum = command::calculate_local_checksum(&entry.id)?.expect("dir should exist");
-            println!("Checksum is {}", checksum);
-            if let Some(import_cs) = entry.checksum {
-                if checksum == import_cs {
-                    println!("OK, match with import checksum");
+            msg!(opts, "Checksum of \"{}\" ({}) is {}", entry.name.as_deref().unwrap_or("<no name>"), entry.id, checksum);
+            item_result.checksum = Some(checksum.clone());
+            if let Some(import_cs) = &entry.checksum {
+                if checksum == *import_cs {
+                    msg!(opts, "OK, match with import checksum");
                 } else {
-                    println!("ERROR, checksum mismatch - {} local <=> import {}", checksum, import_cs);
+                    let mismatch = format!("checksum mismatch - {} local <=> import {}", checksum, import_cs);
+                    msg!(opts, "ERROR, {}", mismatch);
                     errors += 1;
+                    item_result.status = ItemStatus::ChecksumMismatch;
+                    item_result.error = Some(mismatch);
+                    item_result.file_diff = command::diff_local_files(&entry, true).unwrap_or_else(|e| {
+                        warn!("Failed to diff files of {}: {}", entry.id, e);
+                        None
+                    });
+                    if let Some(file_diff) = &item_result.file_diff {
+                        print_file_diff(file_diff, opts);
+                    }
                 }
             }
         }
+        item_results.push(item_result);
     }
 
     if errors != 0 {
-        println!("Done with {} errors", errors);
+        msg!(opts, "Done with {} errors", errors);
     } else {
-        println!("Done");
+        msg!(opts, "Done");
+    }
+
+    Ok(item_results)
+}
+
+fn failed_item_result(entry: Mod, reason: String) -> ItemResult {
+    ItemResult {
+        id: entry.id,
+        name: entry.name,
+        status: ItemStatus::DownloadFailed,
+        checksum: None,
+        error: Some(reason),
+        file_diff: None,
     }
+}
 
-    Ok(())
+/// Run one steamcmd session downloading all of `ids`, returning the per-item results it reported
+fn run_download_session(ids: &[String]) -> Result<HashMap<String, DownloadResult>> {
+    let mut session = command::download_workshop_items(ids)?;
+    let lines = session.take_output();
+    let reader = std::thread::spawn(move || {
+        let mut results = HashMap::new();
+        for line in lines {
+            info!("{}", line);
+            if let Some((id, result)) = command::parse_download_result(&line) {
+                results.insert(id, result);
+            }
+        }
+        results
+    });
+    // steamcmd exits non-zero if any item failed, the per-item results are what matter
+    match session.wait() {
+        Ok(()) => (),
+        Err(Error::WorkerExitCode(code)) => warn!("steamcmd session exited with code {}", code),
+        Err(e) => return Err(e),
+    }
+    Ok(reader.join().expect("steamcmd output reader panicked"))
 }
 
 #[derive(Parser)]
+#[command(after_help = "Exit codes: 0 success, 1 error, 3 some downloads failed, 4 checksum mismatch or verify found differences, 5 Steam Web API error")]
 struct Cli {
     #[command(subcommand)]
-    command: CliCommand
+    command: CliCommand,
+
+    /// Answer yes to all prompts. Implied when stdin is not a terminal
+    #[arg(short, long, visible_alias = "assume-yes", global = true)]
+    yes: bool,
+
+    /// Output format. With json, a single report is written to stdout and progress goes to stderr
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Human, global = true)]
+    output: OutputFormat,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Human,
+    Json,
 }
 
 #[derive(Subcommand)]
 enum CliCommand {
     Init,
-    Import(FileArg),
-    Install(ItemId),
-    Export(FileArg),
-    Update,
+    Import(ImportArgs),
+    Install(InstallArgs),
+    Export(ExportArgs),
+    Update(UpdateArgs),
+    /// Show what update would do, without downloading anything
+    Outdated,
     Cleanup,
+    Uninstall(UninstallArgs),
+    List(ListArgs),
+    /// Check the installed collection against a manifest, without downloading anything
+    Verify(FileArg),
+    /// Resolve a desired set file into a lock file of exact revisions and checksums
+    Lock(LockArgs),
+    /// Install exactly what a lock file records, removing installed items it doesn't have unless held
+    Sync(SyncArgs),
+    /// Point the game at the installed collection and enable it in load order
+    Activate(ActivateArgs),
+    /// Restore a version of an item kept from before it was replaced
+    Rollback(RollbackArgs),
+    /// Freeze items at their installed version, so update and import skip them. Lists held items if none are given
+    Hold(HoldArgs),
+    /// Let held items update again
+    Unhold(UnholdArgs),
+    /// Keep and restore snapshots of the whole collection
+    Snapshot(SnapshotArgs),
 }
 
 #[derive(Args)]
@@ -298,6 +1506,322 @@ struct FileArg {
 }
 
 #[derive(Args)]
-struct ItemId {
-    id: u32,
+struct ListArgs {
+    /// Only show items with this tag
+    #[arg(long)]
+    tag: Option<String>,
+
+    /// Only show items whose supported version matches this glob, e.g. 3.12.*
+    #[arg(long)]
+    supported_version: Option<String>,
+
+    /// Only show items whose name contains this, ignoring case
+    #[arg(long)]
+    name: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = ListSort::Name)]
+    sort: ListSort,
+
+    /// Reverse the sort order
+    #[arg(long)]
+    reverse: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy)]
+enum ListSort {
+    Name,
+    Id,
+    Size,
+    Installed,
+}
+
+#[derive(Args)]
+struct UninstallArgs {
+    /// Workshop items to remove, as ids or URLs
+    #[arg(required = true, value_parser = parse_workshop_id)]
+    ids: Vec<String>,
+
+    /// Remove items even if other installed items depend on them
+    #[arg(long)]
+    force: bool,
+
+    /// Also remove dependencies of the removed items that nothing else needs any more
+    #[arg(long)]
+    remove_orphans: bool,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    file: String,
+
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    format: ManifestFormat,
+
+    /// Also record the path, size and digest of every file, so differences can be pinpointed
+    #[arg(long)]
+    detailed: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum ManifestFormat {
+    /// ironworks manifest
+    Json,
+    /// Irony Mod Manager collection export zip, preserving load order
+    Irony,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    /// Manifest, or collection with --format irony, to import
+    #[arg(required_unless_present = "from_launcher", conflicts_with = "from_launcher")]
+    file: Option<String>,
+
+    /// Install the mods of this Paradox Launcher playset instead, by name
+    #[arg(long, value_name = "PLAYSET")]
+    from_launcher: Option<String>,
+
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    format: ManifestFormat,
+
+    /// Only show what would be downloaded
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct InstallArgs {
+    /// Workshop items or collections, as ids, steamcommunity.com URLs or steam:// URLs
+    #[arg(required = true, value_parser = parse_workshop_id)]
+    ids: Vec<String>,
+
+    /// Only show what would be downloaded
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct LockArgs {
+    /// Desired set file. The lock is written next to it with a .lock extension
+    #[arg(default_value = "ironworks.toml")]
+    file: String,
+
+    /// Bring unpinned items up to date from the Workshop before locking, instead of locking what is installed
+    #[arg(long)]
+    update: bool,
+
+    /// Only show what would be downloaded
+    #[arg(long, requires = "update")]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct SyncArgs {
+    /// Desired set file, whose lock file is installed
+    #[arg(default_value = "ironworks.toml")]
+    file: String,
+
+    /// Only show what would be downloaded
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct ActivateArgs {
+    /// Also write the load order as a Paradox Launcher playset with this name, replacing any of the same name
+    #[arg(long)]
+    playset: Option<String>,
+}
+
+#[derive(Args)]
+struct RollbackArgs {
+    /// Workshop item to roll back, as an id or URL
+    #[arg(value_parser = parse_workshop_id)]
+    id: String,
+
+    /// Roll back to the latest kept version installed at or before this time,
+    /// as a Unix timestamp, a date, "YYYY-MM-DD HH:MM:SS" or RFC 3339, in UTC unless given
+    #[arg(long, value_parser = parse_timestamp)]
+    to: Option<i64>,
+
+    /// Only list the kept versions
+    #[arg(long, conflicts_with = "to")]
+    list: bool,
+}
+
+#[derive(Args)]
+struct HoldArgs {
+    /// Workshop items to hold, as ids or URLs
+    #[arg(value_parser = parse_workshop_id)]
+    ids: Vec<String>,
+}
+
+#[derive(Args)]
+struct UnholdArgs {
+    /// Workshop items to unhold, as ids or URLs
+    #[arg(required = true, value_parser = parse_workshop_id)]
+    ids: Vec<String>,
+}
+
+#[derive(Args)]
+struct SnapshotArgs {
+    #[command(subcommand)]
+    command: SnapshotCommand,
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Snapshot every installed item, sharing files with the collection where the filesystem supports copy-on-write
+    Create(SnapshotCreateArgs),
+    /// List snapshots, oldest first
+    List,
+    /// Put the collection back exactly as it was when a snapshot was taken
+    Restore(SnapshotRestoreArgs),
+    Delete(SnapshotNameArg),
+}
+
+#[derive(Args)]
+struct SnapshotCreateArgs {
+    /// Defaults to the current time
+    name: Option<String>,
+}
+
+#[derive(Args)]
+struct SnapshotRestoreArgs {
+    name: String,
+
+    /// Only show what would be restored and removed
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args)]
+struct SnapshotNameArg {
+    name: String,
+}
+
+#[derive(Args)]
+struct UpdateArgs {
+    /// Only show what would be downloaded
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Accept a Unix timestamp, a date meaning the end of that day, a date and time, or an RFC 3339 time
+fn parse_timestamp(s: &str) -> std::result::Result<i64, String> {
+    let s = s.trim();
+    if let Ok(ts) = s.parse::<i64>() {
+        return Ok(ts);
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.timestamp());
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%F %X") {
+        return Ok(dt.and_utc().timestamp());
+    }
+    if let Some(dt) = NaiveDate::parse_from_str(s, "%F").ok().and_then(|d| d.and_hms_opt(23, 59, 59)) {
+        return Ok(dt.and_utc().timestamp());
+    }
+    Err(format!("'{}' is not a timestamp or date", s))
+}
+
+/// Accept either a bare workshop id, a Steam Community file details URL with an `id` query parameter,
+/// or a `steam://` URL opening either of those
+fn parse_workshop_id(s: &str) -> std::result::Result<String, String> {
+    // workshop ids have outgrown u32, but are still u64
+    let as_id = |id: &str| id.parse::<u64>().ok().map(|id| id.to_string());
+    let s = s.trim();
+    if let Some(id) = as_id(s) {
+        return Ok(id);
+    }
+    // steam://url/CommunityFilePage/<id>
+    if let Some(id) = s.strip_prefix("steam://url/CommunityFilePage/").and_then(|id| as_id(id.trim_end_matches('/'))) {
+        return Ok(id);
+    }
+    // steam://openurl/<https url>
+    let url = s.strip_prefix("steam://openurl/").unwrap_or(s);
+    if let Ok(url) = reqwest::Url::parse(url) {
+        if url.host_str().is_some_and(|host| host == "steamcommunity.com" || host.ends_with(".steamcommunity.com")) {
+            if let Some(id) = url.query_pairs().find(|(k, _)| k == "id").and_then(|(_, id)| as_id(&id)) {
+                return Ok(id);
+            }
+        }
+    }
+    Err(format!("'{}' is not a workshop id or URL", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_workshop_id_accepts_ids_and_urls() {
+        let id = Ok("2880030227".to_owned());
+        assert_eq!(parse_workshop_id("2880030227"), id);
+        assert_eq!(parse_workshop_id(" 2880030227 "), id);
+        assert_eq!(parse_workshop_id("https://steamcommunity.com/sharedfiles/filedetails/?id=2880030227"), id);
+        assert_eq!(parse_workshop_id("https://steamcommunity.com/workshop/filedetails/?searchtext=&id=2880030227"), id);
+        assert_eq!(parse_workshop_id("steam://url/CommunityFilePage/2880030227/"), id);
+        assert_eq!(parse_workshop_id("steam://openurl/https://steamcommunity.com/sharedfiles/filedetails/?id=2880030227"), id);
+        // beyond u32
+        assert_eq!(parse_workshop_id("18446744073709551615"), Ok("18446744073709551615".to_owned()));
+    }
+
+    #[test]
+    fn parse_workshop_id_rejects_everything_else() {
+        assert!(parse_workshop_id("").is_err());
+        assert!(parse_workshop_id("-1").is_err());
+        assert!(parse_workshop_id("abc").is_err());
+        assert!(parse_workshop_id("https://steamcommunity.com/sharedfiles/filedetails/").is_err());
+        assert!(parse_workshop_id("https://steamcommunity.com/sharedfiles/filedetails/?id=abc").is_err());
+        assert!(parse_workshop_id("https://example.com/sharedfiles/filedetails/?id=2880030227").is_err());
+        assert!(parse_workshop_id("https://notsteamcommunity.com/?id=2880030227").is_err());
+    }
+
+    #[test]
+    fn is_dependency_keeps_recorded_flag() {
+        let installed = |dependency| schemas::InstalledItem {
+            title: None,
+            time_updated: None,
+            installed_at: 0,
+            checksum: String::new(),
+            checksum_version: ChecksumVersion::LATEST,
+            dependency,
+            workshop_children: vec![],
+            rolled_back_from: None,
+        };
+        let mut state = schemas::InstallState::default();
+        state.items.insert("111".to_owned(), installed(true));
+        state.items.insert("222".to_owned(), installed(false));
+        let requested = ["111", "222", "333"].map(str::to_owned).into_iter().collect();
+        assert!(is_dependency(&state, &requested, "111"));
+        assert!(!is_dependency(&state, &requested, "222"));
+        assert!(!is_dependency(&state, &requested, "333"));
+        assert!(is_dependency(&state, &HashSet::new(), "444"));
+    }
+
+    #[test]
+    fn glob_match_wildcards() {
+        assert!(glob_match("3.12.*", "3.12.4"));
+        assert!(glob_match("3.12.*", "3.12."));
+        assert!(!glob_match("3.12.*", "3.1.2"));
+        assert!(glob_match("v3.?.*", "v3.9.1"));
+        assert!(!glob_match("v3.?.*", "v3.10.1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("*a*b", "xaxxa"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn parse_timestamp_formats() {
+        assert_eq!(parse_timestamp("1700000000"), Ok(1700000000));
+        assert_eq!(parse_timestamp("2023-11-14T22:13:20Z"), Ok(1700000000));
+        assert_eq!(parse_timestamp("2023-11-15T00:13:20+02:00"), Ok(1700000000));
+        assert_eq!(parse_timestamp("2023-11-14 22:13:20"), Ok(1700000000));
+        // a bare date means the end of that day
+        assert_eq!(parse_timestamp("2023-11-14"), Ok(1700006399));
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("2023-13-01").is_err());
+    }
 }
@@ -1,19 +1,467 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
 use jomini::JominiDeserialize;
 use serde::{Serialize, Deserialize};
 
+/// Version of the manifest format written by this build
+pub const MANIFEST_VERSION: u32 = 2;
+
 #[derive(Deserialize, Serialize)]
 pub struct Manifest {
+    /// Format version. Manifests from before this was recorded are v1, with only id, name and checksum
+    #[serde(default = "manifest_v1")]
+    pub version: u32,
     pub mods: Vec<Mod>,
 }
 
-#[derive(Deserialize, Serialize, Clone)]
+fn manifest_v1() -> u32 {
+    1
+}
+
+/// Schema of `exported.json` inside an Irony Mod Manager collection export zip
+#[derive(Deserialize, Serialize, Default)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct IronyCollection {
+    pub name: String,
+    pub game: String,
+    /// Descriptor names, e.g. `mod/ugc_<id>.mod`, in load order
+    pub mods: Vec<String>,
+    /// Display names, in the same order as `mods`
+    pub mod_names: Vec<String>,
+    /// Source ids, in the same order as `mods`
+    pub mod_ids: Vec<IronyModId>,
+    pub is_selected: bool,
+    pub merged_folder_name: Option<String>,
+    pub patch_mod_enabled: bool,
+}
+
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(rename_all = "PascalCase", default)]
+pub struct IronyModId {
+    pub paradox_id: Option<u64>,
+    pub steam_id: Option<u64>,
+}
+
+/// Schema of the hand-edited desired set file, from which a lock file is generated
+#[derive(Deserialize)]
+pub struct DesiredSet {
+    #[serde(default)]
+    pub mods: Vec<DesiredMod>,
+}
+
+#[derive(Deserialize)]
+pub struct DesiredMod {
+    /// Workshop id or URL
+    pub id: String,
+    /// Keep the locked revision of this item when the lock is updated
+    #[serde(default)]
+    pub pin: bool,
+    /// Free text for whoever maintains the file
+    pub note: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 pub struct Mod {
     pub id: String,
     pub name: Option<String>,
     pub checksum: Option<String>,
+    /// Algorithm `checksum` was calculated with. Manifests from before this was recorded are v1
+    #[serde(default)]
+    pub checksum_version: ChecksumVersion,
+    /// Per-file digests, only present in detailed manifests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<Vec<FileDigest>>,
+    /// Workshop `time_updated` of the exported revision, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_updated: Option<i64>,
+    /// Whether the item was pulled in as a dependency, rather than requested itself
+    #[serde(default)]
+    pub dependency: bool,
+    /// Workshop ids of the other items in the manifest this one depends on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    /// `version` from the item's descriptor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// `supported_version` from the item's descriptor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supported_version: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumVersion {
+    /// `b64(SHA256(concat(map(SHA256, [file_contents]))))` in walk order, skipping unreadable files
+    #[default]
+    V1,
+    /// `b64(SHA256(concat(map(path + '\0' + SHA256, [files]))))` sorted by normalised relative path
+    V2,
+}
+
+impl ChecksumVersion {
+    pub const LATEST: ChecksumVersion = ChecksumVersion::V2;
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct FileDigest {
+    /// Path relative to the mod folder, with `/` separators
+    pub path: String,
+    pub size: u64,
+    /// b64 of the SHA256 of the file contents
+    pub sha256: String,
+}
+
+/// Differences in files of a mod folder, from the point of view of the local copy
+#[derive(Serialize, Clone, Default)]
+pub struct FileDiff {
+    /// Files present locally but not in the manifest
+    pub added: Vec<String>,
+    /// Files in the manifest but not present locally
+    pub removed: Vec<String>,
+    /// Files present in both with different contents
+    pub changed: Vec<String>,
+}
+
+/// Document written to stdout by `--output json`
+#[derive(Serialize)]
+pub struct Report {
+    #[serde(flatten)]
+    pub command: Option<CommandReport>,
+    /// Set if the command aborted with an error
+    pub error: Option<String>,
+    pub exit_code: u8,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum CommandReport {
+    Init,
+    Import(SyncReport),
+    Install(SyncReport),
+    Export(ExportReport),
+    Update(SyncReport),
+    Outdated(SyncReport),
+    Cleanup,
+    Uninstall(UninstallReport),
+    List(ListReport),
+    Verify(VerifyReport),
+    Lock(LockReport),
+    Sync(SyncReport),
+    Activate(ActivateReport),
+    Rollback(RollbackReport),
+    Hold(HoldReport),
+    Unhold(HoldReport),
+    Snapshot(SnapshotReport),
+}
+
+/// Report of a command bringing the collection in line with a set of desired items
+#[derive(Serialize, Default)]
+pub struct SyncReport {
+    pub plan: Vec<PlannedItem>,
+    /// Workshop ids the Steam Web API returned no details for
+    pub missing: Vec<String>,
+    /// Whether the plan was declined at the confirmation prompt
+    pub aborted: bool,
+    /// Whether only the plan was computed, without downloading anything
+    pub dry_run: bool,
+    pub results: Vec<ItemResult>,
+}
+
+#[derive(Serialize)]
+pub struct PlannedItem {
+    pub id: String,
+    pub name: Option<String>,
+    pub action: PlannedAction,
+    pub reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_diff: Option<FileDiff>,
+    /// Whether the item was pulled in as a dependency of a requested item, rather than requested itself
+    pub dependency: bool,
+    /// Workshop `time_updated` of the latest revision
+    pub remote_time_updated: Option<i64>,
+    /// Workshop `time_updated` of the installed revision
+    pub local_time_updated: Option<i64>,
+    pub expected_checksum: Option<String>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedAction {
+    Download,
+    Skip,
+    Remove,
+}
+
+#[derive(Serialize)]
+pub struct ItemResult {
+    pub id: String,
+    pub name: Option<String>,
+    pub status: ItemStatus,
+    pub checksum: Option<String>,
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_diff: Option<FileDiff>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    Installed,
+    DownloadFailed,
+    ChecksumMismatch,
+    Removed,
+}
+
+#[derive(Serialize, Default)]
+pub struct UninstallReport {
+    pub removed: Vec<UninstalledItem>,
+    /// Dependencies no longer needed by anything left installed, but kept because they were requested or are held
+    pub kept: Vec<String>,
+    /// Whether the removal was declined at the confirmation prompt
+    pub aborted: bool,
+}
+
+#[derive(Serialize)]
+pub struct UninstalledItem {
+    pub id: String,
+    pub name: Option<String>,
+    /// Whether the item was removed as a dependency no longer needed, rather than requested
+    pub orphan: bool,
+    /// Remaining installed items that still depend on this one, only when forced
+    pub required_by: Vec<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct VerifyReport {
+    pub items: Vec<VerifiedItem>,
+}
+
+#[derive(Serialize)]
+pub struct VerifiedItem {
+    pub id: String,
+    pub name: Option<String>,
+    pub status: VerifyStatus,
+    pub expected_checksum: Option<String>,
+    pub local_checksum: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_diff: Option<FileDiff>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VerifyStatus {
+    Match,
+    Mismatch,
+    /// In the manifest but not installed
+    Missing,
+    /// Installed, but the manifest has no checksum to compare against
+    Unverified,
+    /// Installed but not in the manifest
+    Extra,
+}
+
+#[derive(Serialize, Default)]
+pub struct ListReport {
+    pub items: Vec<ListedItem>,
+}
+
+#[derive(Serialize)]
+pub struct ListedItem {
+    pub id: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub supported_version: Option<String>,
+    pub tags: Vec<String>,
+    /// Size on disk in bytes
+    pub size: u64,
+    /// Unix timestamp of when the item was installed, if known
+    pub installed_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ExportReport {
+    pub file: String,
+    pub manifest: Manifest,
+}
+
+#[derive(Serialize)]
+pub struct LockReport {
+    /// Path of the lock file
+    pub file: String,
+    /// What bringing the unpinned items up to date did, only with `--update`
+    pub update: Option<SyncReport>,
+    /// The lock written, or `None` if nothing was written
+    pub manifest: Option<Manifest>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ActivateReport {
+    /// Items enabled in the game, in load order
+    pub enabled: Vec<ActivatedItem>,
+    /// Items whose descriptors were removed from the game because they are no longer installed
+    pub removed: Vec<String>,
+    /// Path of the `dlc_load.json` written
+    pub dlc_load: String,
+    /// Name of the Paradox Launcher playset written, if any
+    pub playset: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ActivatedItem {
+    pub id: String,
+    pub name: String,
+    /// Name the game knows the item by, e.g. `mod/ironworks_<id>.mod`
+    pub descriptor: String,
+}
+
+#[derive(Serialize)]
+pub struct RollbackReport {
+    pub id: String,
+    /// Versions that were kept before rolling back, oldest first
+    pub available: Vec<RetainedVersion>,
+    /// The version rolled back to, if any
+    pub restored: Option<RetainedVersion>,
+}
+
+#[derive(Serialize, Default)]
+pub struct HoldReport {
+    /// Items whose hold was changed by the command
+    pub changed: Vec<String>,
+    /// All items held afterwards
+    pub held: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotReport {
+    pub action: SnapshotAction,
+    /// Snapshot created, restored or deleted, or every snapshot when listing
+    pub snapshots: Vec<SnapshotSummary>,
+    /// Items put back from the snapshot by a restore
+    pub restored: Vec<String>,
+    /// Items removed by a restore because the snapshot doesn't have them
+    pub removed: Vec<String>,
+    /// Items left as installed by a restore because their copies in the snapshot have been modified
+    pub damaged: Vec<String>,
+    /// Bytes that had to be copied when creating, rather than shared with the collection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copied_size: Option<u64>,
+    /// Whether the restore was declined at the confirmation prompt
+    pub aborted: bool,
+    /// Whether only the changes a restore would make were computed
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotAction {
+    Create,
+    List,
+    Restore,
+    Delete,
+}
+
+#[derive(Serialize)]
+pub struct SnapshotSummary {
+    pub name: String,
+    pub created_at: i64,
+    pub items: usize,
+    /// Total size of the snapshotted items in bytes, including what is shared with the collection
+    pub size: u64,
+}
+
+/// Schema of the install state file kept inside the collection directory
+#[derive(Deserialize, Serialize, Default)]
+pub struct InstallState {
+    pub items: BTreeMap<String, InstalledItem>,
+    /// Workshop ids in the load order they were last imported with, if any
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub load_order: Vec<String>,
+    /// Previous versions kept when items were replaced
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub retained: BTreeMap<String, Vec<RetainedVersion>>,
+    /// Items frozen at their installed version, skipped by update and import
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub held: BTreeSet<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, PartialEq)]
+pub struct InstalledItem {
+    pub title: Option<String>,
+    /// Workshop `time_updated` of the installed revision, if known at install time
+    pub time_updated: Option<i64>,
+    /// Unix timestamp of when the item was copied into the collection
+    pub installed_at: i64,
+    pub checksum: String,
+    #[serde(default)]
+    pub checksum_version: ChecksumVersion,
+    /// Whether the item was installed as a dependency, rather than requested itself
+    #[serde(default)]
+    pub dependency: bool,
+    /// Workshop children of the installed revision
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub workshop_children: Vec<String>,
+    /// Workshop `time_updated` of the revision this was rolled back from, which shouldn't be offered as an update again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolled_back_from: Option<i64>,
+}
+
+/// A replaced version of an item, kept so it can be rolled back to
+#[derive(Deserialize, Serialize, Clone)]
+pub struct RetainedVersion {
+    pub title: Option<String>,
+    pub time_updated: Option<i64>,
+    /// Unix timestamp of when this version was installed, which also names its folder
+    pub installed_at: i64,
+    pub checksum: String,
+    #[serde(default)]
+    pub checksum_version: ChecksumVersion,
+    /// Unix timestamp of when this version was replaced
+    pub retained_at: i64,
+    /// Size on disk in bytes
+    pub size: u64,
+}
+
+/// Schema of `snapshot.json`, describing a snapshot of the whole collection kept next to copies of its items
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    pub name: String,
+    /// Unix timestamp of when the snapshot was taken
+    pub created_at: i64,
+    pub items: BTreeMap<String, SnapshotItem>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub load_order: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SnapshotItem {
+    /// Checksum of the item when snapshotted, which its copy is checked against before restoring
+    pub checksum: String,
+    #[serde(default)]
+    pub checksum_version: ChecksumVersion,
+    pub size: u64,
+    /// Install record of the item, if it had one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installed: Option<InstalledItem>,
+}
+
+/// Schema of the per-file digest cache kept inside the collection directory,
+/// keyed by `/` separated path relative to the collection
+#[derive(Deserialize, Serialize, Default)]
+pub struct DigestCache {
+    pub files: HashMap<String, CachedDigest>,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+pub struct CachedDigest {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub mtime: u64,
+    /// Base64 encoded SHA256 of the file contents
+    pub sha256: String,
 }
 
 /// Schema of descriptor.mod file
+#[allow(dead_code)]
 #[derive(JominiDeserialize)]
 pub struct Descriptor {
     pub name: String,
@@ -28,6 +476,41 @@ pub struct Descriptor {
 pub struct Config {
     pub collection_path: String,
     pub steam_webapi_key: String,
+    /// Override for the steamcmd installer download, e.g. to point at a local mirror.
+    /// Must be the same archive format as the platform default.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub steamcmd_installer_url: Option<String>,
+    /// Override for the Stellaris user directory holding the launcher database and `mod/` folder,
+    /// e.g. when Documents has been moved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stellaris_user_dir: Option<String>,
+    /// Number of previous versions of each item to keep when it is replaced, defaults to 1. 0 keeps none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained_versions: Option<usize>,
+    /// Cap on the total size of all kept previous versions in megabytes, dropping the oldest first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained_versions_max_mb: Option<u64>,
+    /// How downloaded items are put into the collection, defaults to copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_strategy: Option<InstallStrategy>,
+}
+
+/// How a downloaded item gets from steamcmd's workshop folder into the collection.
+/// Anything the filesystem can't do falls back to copying
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallStrategy {
+    /// Copy every file, leaving steamcmd's copy for incremental updates at the cost of twice the disk space
+    #[default]
+    Copy,
+    /// Move steamcmd's copy into the collection, so every update is a full download
+    Move,
+    /// Hard link every file to steamcmd's copy. Editing a file in one changes it in the other
+    Hardlink,
+    /// Share every file's contents with steamcmd's copy until either changes, on filesystems such as Btrfs, XFS and APFS
+    Reflink,
+    /// Link the item's folder to steamcmd's copy. Previous versions can't be kept, and cleanup leaves linked items alone
+    Symlink,
 }
 
 #[derive(Deserialize)]
@@ -60,3 +543,31 @@ pub struct PublishedFileDetails {
 pub struct PublishedFileChild {
     pub publishedfileid: String,
 }
+
+#[derive(Deserialize)]
+pub struct GetCollectionDetailsResponse {
+    pub response: GetCollectionDetailsResponseInner,
+}
+
+#[derive(Deserialize)]
+pub struct GetCollectionDetailsResponseInner {
+    pub collectiondetails: Vec<CollectionDetails>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CollectionDetails {
+    pub publishedfileid: String,
+    /// Absent if the file is not a collection
+    pub children: Option<Vec<CollectionChild>>,
+}
+
+/// Workshop file type of collections, as opposed to 0 for regular items
+pub const COLLECTION_FILETYPE: u32 = 2;
+
+#[derive(Deserialize, Clone)]
+pub struct CollectionChild {
+    pub publishedfileid: String,
+    pub sortorder: u32,
+    /// `COLLECTION_FILETYPE` for a nested collection
+    pub filetype: u32,
+}
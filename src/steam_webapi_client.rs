@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 
-use log::trace;
+use log::{trace, warn};
 use reqwest::Method;
+use serde::de::DeserializeOwned;
 
-use crate::{error::Result, schemas::{GetPublishedFileDetailsResponse, GetPublishedFileDetailsResponseItem, PublishedFileDetails}};
+use crate::{error::{Error, Result}, schemas::{CollectionDetails, GetCollectionDetailsResponse, GetPublishedFileDetailsResponse, GetPublishedFileDetailsResponseItem}};
 
 pub struct SteamWebApiClient {
     client: reqwest::Client,
@@ -12,6 +13,7 @@ pub struct SteamWebApiClient {
 
 const STELLARIS_APPID: &str = "281990";
 const STEAM_WEBAPI_GETDETAILS_URL: &str = "https://api.steampowered.com/IPublishedFileService/GetDetails/v1/";
+const STEAM_WEBAPI_GETCOLLECTIONDETAILS_URL: &str = "https://api.steampowered.com/ISteamRemoteStorage/GetCollectionDetails/v1/";
 
 impl SteamWebApiClient {
     pub fn new(webapi_key: impl AsRef<str>) -> SteamWebApiClient {
@@ -29,10 +31,8 @@ impl SteamWebApiClient {
                 ("short_description", "true"),
                 ("appid", STELLARIS_APPID),
             ]);
-        let mut i = 0;
-        for file_id in file_ids {
+        for (i, file_id) in file_ids.enumerate() {
             builder = builder.query(&[(format!("publishedfileids[{}]", i), file_id.as_ref())]);
-            i += 1;
         }
         let req = builder.build()?;
         trace!("Request to SteamApi:");
@@ -41,14 +41,48 @@ impl SteamWebApiClient {
         let text = resp.text().await?;
         trace!("Response from SteamApi:");
         trace!("{}", text);
-        Ok(serde_json::from_str::<GetPublishedFileDetailsResponse>(&text)?.response.publishedfiledetails.into_iter()
+        Ok(parse_response::<GetPublishedFileDetailsResponse>(&text)?.response.publishedfiledetails.into_iter()
             .map(|d| {
                 match d {
                     GetPublishedFileDetailsResponseItem::FileDetails(ref fd) => (fd.publishedfileid.clone(), d.clone()),
-                    GetPublishedFileDetailsResponseItem::MissingItem { result: 9, publishedfileid: ref id } => (id.clone(), d.clone()),
-                    _ => panic!("bad deserialisation result"),
+                    // 9 is "file not found", anything else is just as unusable, so report it as missing too
+                    GetPublishedFileDetailsResponseItem::MissingItem { result, publishedfileid: ref id } => {
+                        if result != 9 {
+                            warn!("Steam Web API returned result {} for item {}", result, id);
+                        }
+                        (id.clone(), d.clone())
+                    }
                 }
             })
             .collect())
     }
+
+    /// Fetch the members of workshop collections. Ids that aren't collections come back with no children
+    pub async fn get_collection_details(&self, file_ids: impl Iterator<Item = impl AsRef<str>>) -> Result<HashMap<String, CollectionDetails>> {
+        let file_ids = file_ids.map(|id| id.as_ref().to_owned()).collect::<Vec<_>>();
+        let mut form = vec![
+            ("key".to_owned(), self.webapi_key.clone()),
+            ("collectioncount".to_owned(), file_ids.len().to_string()),
+        ];
+        for (i, file_id) in file_ids.into_iter().enumerate() {
+            form.push((format!("publishedfileids[{}]", i), file_id));
+        }
+        let req = self.client.request(Method::POST, STEAM_WEBAPI_GETCOLLECTIONDETAILS_URL)
+            .form(&form)
+            .build()?;
+        trace!("Request to SteamApi:");
+        trace!("{}", req.url());
+        let resp = self.client.execute(req).await?.error_for_status()?;
+        let text = resp.text().await?;
+        trace!("Response from SteamApi:");
+        trace!("{}", text);
+        Ok(parse_response::<GetCollectionDetailsResponse>(&text)?.response.collectiondetails.into_iter()
+            .map(|d| (d.publishedfileid.clone(), d))
+            .collect())
+    }
+}
+
+/// Deserialise a response body, reporting anything unexpected as a Web API error rather than a local one
+fn parse_response<T: DeserializeOwned>(text: &str) -> Result<T> {
+    serde_json::from_str(text).map_err(|e| Error::WebApi(format!("unexpected response: {}", e)))
 }
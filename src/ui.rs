@@ -3,8 +3,6 @@ use std::io;
 use crossterm::{terminal::{EnterAlternateScreen, LeaveAlternateScreen}, event::{EnableMouseCapture, DisableMouseCapture}};
 use ratatui::{backend::CrosstermBackend, Terminal, widgets::{Block, Borders}};
 
-use crate::command;
-
 pub struct Ui {
 }
 